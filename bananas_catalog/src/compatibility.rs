@@ -0,0 +1,97 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use super::version::BranchVersion;
+
+/* The branch OpenTTD's own releases are on; clients that send branches call
+ * it this, and so does the index. Clients that only send a version are on it
+ * too. */
+pub const MAIN_BRANCH: &str = "vanilla";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+/* A single condition on a branch version, like ">= 1.10.0". */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Condition {
+    pub operator: Operator,
+    pub version: BranchVersion,
+}
+
+impl Condition {
+    pub fn matches(&self, version: &BranchVersion) -> bool {
+        match self.operator {
+            Operator::Less => *version < self.version,
+            Operator::LessOrEqual => *version <= self.version,
+            Operator::Equal => *version == self.version,
+            Operator::GreaterOrEqual => *version >= self.version,
+            Operator::Greater => *version > self.version,
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (operator, version) = [
+            ("<=", Operator::LessOrEqual),
+            (">=", Operator::GreaterOrEqual),
+            ("<", Operator::Less),
+            (">", Operator::Greater),
+            ("=", Operator::Equal),
+        ]
+        .into_iter()
+        .find_map(|(prefix, operator)| s.strip_prefix(prefix).map(|rest| (operator, rest)))
+        .ok_or_else(|| format!("condition '{}' has no valid operator", s))?;
+
+        let version = version
+            .trim()
+            .parse()
+            .map_err(|e| format!("condition '{}': {}", s, e))?;
+
+        Ok(Condition { operator, version })
+    }
+}
+
+/* The conditions a version of a branch has to meet to be able to load the
+ * content. */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Compatibility {
+    pub name: String,
+    pub conditions: Vec<Condition>,
+}
+
+impl Compatibility {
+    pub fn matches(&self, version: &BranchVersion) -> bool {
+        self.conditions
+            .iter()
+            .all(|condition| condition.matches(version))
+    }
+}
+
+/* Content without any compatibility information runs everywhere. Otherwise
+ * one of the client's branches has to meet all conditions set for it. */
+pub fn is_compatible<'a, I>(compatibility: &[Compatibility], branches: I) -> bool
+where
+    I: IntoIterator<Item = (&'a str, &'a BranchVersion)>,
+{
+    if compatibility.is_empty() {
+        return true;
+    }
+
+    branches.into_iter().any(|(name, version)| {
+        compatibility
+            .iter()
+            .filter(|compatibility| compatibility.name == name)
+            .any(|compatibility| compatibility.matches(version))
+    })
+}
@@ -0,0 +1,584 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use super::compatibility::{Compatibility, Condition};
+use super::{Availability, Catalog, ContentType, Package, Version, MD5_PARTIAL_SIZE};
+
+/* Every error points at the file (or folder) that caused it. */
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, std::io::Error),
+    Yaml(PathBuf, serde_yaml::Error),
+    Invalid(PathBuf, String),
+    /* Everything that is wrong with the index, so it can be fixed in one go. */
+    Multiple(Vec<Error>),
+}
+
+impl Error {
+    fn from_errors(mut errors: Vec<Error>) -> Result<(), Error> {
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(Error::Multiple(errors)),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(path, e) => write!(formatter, "{}: {}", path.display(), e),
+            Error::Yaml(path, e) => write!(formatter, "{}: {}", path.display(), e),
+            Error::Invalid(path, msg) => write!(formatter, "{}: {}", path.display(), msg),
+            Error::Multiple(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(formatter, "{}", errors.join("\n"))
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct GlobalYaml {
+    name: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    url: String,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct VersionYaml {
+    version: String,
+    upload_date: String,
+    md5sum_partial: String,
+    filesize: u32,
+    availability: Availability,
+    #[serde(default)]
+    compatibility: Vec<CompatibilityYaml>,
+    #[serde(default)]
+    dependencies: Vec<DependencyYaml>,
+}
+
+#[derive(Deserialize)]
+struct CompatibilityYaml {
+    name: String,
+    conditions: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct DependencyYaml {
+    content_type: String,
+    unique_id: String,
+    md5sum_partial: String,
+}
+
+/* Dependencies can only be resolved once every package is loaded. */
+struct PendingDependencies {
+    content_id: u32,
+    path: PathBuf,
+    dependencies: Vec<DependencyYaml>,
+}
+
+/* Where the file of a version lives inside the storage folder. */
+pub fn storage_path(storage: &Path, package: &Package, version: &Version) -> PathBuf {
+    storage
+        .join(folder_name(package.content_type))
+        .join(format!("{:08x}", package.unique_id))
+        .join(format!("{}.tar.gz", to_hex(&version.md5)))
+}
+
+/* Load a BaNaNaS index: "<content-type>/<unique-id>/global.yaml" describes a
+ * package, and every YAML file in "<content-type>/<unique-id>/versions" one of its
+ * versions. The storage folder holds the files as
+ * "<content-type>/<unique-id>/<md5sum>.tar.gz"; it is needed to complete the
+ * md5sum, as the index only stores the first few bytes of it.
+ *
+ * content_ids are numbered in load order. Adding or removing a package
+ * renumbers everything after it, so a content_id is only meaningful for the
+ * lifetime of the returned Catalog; OpenTTD asks for them again every time it
+ * connects, so that is enough. */
+pub fn load(index: &Path, storage: &Path) -> Result<Catalog, Error> {
+    let mut packages = Vec::new();
+    let mut pending = Vec::new();
+    let mut next_content_id = 0;
+
+    for content_type in ContentType::ALL {
+        let folder = index.join(folder_name(content_type));
+        if !folder.is_dir() {
+            continue;
+        }
+
+        for package_folder in sorted_entries(&folder)? {
+            if !package_folder.is_dir() {
+                continue;
+            }
+
+            packages.push(load_package(
+                content_type,
+                &package_folder,
+                storage,
+                &mut next_content_id,
+                &mut pending,
+            )?);
+        }
+    }
+
+    let (paths, mut errors) = resolve_dependencies(&mut packages, pending);
+    errors.extend(check_dependency_cycles(&packages, &paths));
+    Error::from_errors(errors)?;
+
+    Ok(Catalog::new(packages))
+}
+
+fn load_package(
+    content_type: ContentType,
+    folder: &Path,
+    storage: &Path,
+    next_content_id: &mut u32,
+    pending: &mut Vec<PendingDependencies>,
+) -> Result<Package, Error> {
+    let unique_id = folder
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(parse_hex::<4>)
+        .map(u32::from_be_bytes)
+        .ok_or_else(|| Error::Invalid(folder.to_path_buf(), "invalid unique-id".to_string()))?;
+
+    let global: GlobalYaml = read_yaml(&folder.join("global.yaml"))?;
+
+    let mut package = Package {
+        content_type,
+        unique_id,
+        name: global.name,
+        url: global.url,
+        description: global.description,
+        tags: global.tags,
+        versions: Vec::new(),
+    };
+
+    let versions_folder = folder.join("versions");
+    if !versions_folder.is_dir() {
+        return Ok(package);
+    }
+
+    /* Files in storage are named after their full md5sum. */
+    let storage_folder = storage
+        .join(folder_name(content_type))
+        .join(format!("{:08x}", unique_id));
+    let md5sums: Vec<[u8; 16]> = if storage_folder.is_dir() {
+        sorted_entries(&storage_folder)?
+            .iter()
+            .filter_map(|path| path.file_name()?.to_str()?.strip_suffix(".tar.gz"))
+            .filter_map(parse_hex::<16>)
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    for path in sorted_entries(&versions_folder)? {
+        if path.extension().is_none_or(|extension| extension != "yaml") {
+            continue;
+        }
+
+        let version: VersionYaml = read_yaml(&path)?;
+
+        let md5sum_partial = parse_hex::<MD5_PARTIAL_SIZE>(&version.md5sum_partial)
+            .ok_or_else(|| Error::Invalid(path.clone(), "invalid md5sum-partial".to_string()))?;
+        let md5 = md5sums
+            .iter()
+            .find(|md5| md5.starts_with(&md5sum_partial))
+            .ok_or_else(|| {
+                Error::Invalid(
+                    path.clone(),
+                    format!(
+                        "no file for md5sum-partial {} in {}",
+                        version.md5sum_partial,
+                        storage_folder.display()
+                    ),
+                )
+            })?;
+
+        let compatibility = version
+            .compatibility
+            .into_iter()
+            .map(|compatibility| {
+                Ok(Compatibility {
+                    name: compatibility.name,
+                    conditions: compatibility
+                        .conditions
+                        .iter()
+                        .map(|condition| condition.parse::<Condition>())
+                        .collect::<Result<_, _>>()?,
+                })
+            })
+            .collect::<Result<_, String>>()
+            .map_err(|e| Error::Invalid(path.clone(), e))?;
+
+        pending.push(PendingDependencies {
+            content_id: *next_content_id,
+            path: path.clone(),
+            dependencies: version.dependencies,
+        });
+
+        package.versions.push(Version {
+            content_id: *next_content_id,
+            version: version.version,
+            upload_date: version.upload_date,
+            availability: version.availability,
+            md5: *md5,
+            filesize: version.filesize,
+            dependencies: Vec::new(),
+            compatibility,
+        });
+        *next_content_id += 1;
+    }
+
+    Ok(package)
+}
+
+/* Resolve the (content-type, unique-id, md5sum-partial) references of every
+ * version to content_ids. Returns the file every content_id was loaded from,
+ * for use in later error messages, and every dependency that could not be
+ * resolved; those are left out. */
+fn resolve_dependencies(
+    packages: &mut [Package],
+    pending: Vec<PendingDependencies>,
+) -> (HashMap<u32, PathBuf>, Vec<Error>) {
+    let by_unique_id: HashMap<(ContentType, u32), usize> = packages
+        .iter()
+        .enumerate()
+        .map(|(index, package)| ((package.content_type, package.unique_id), index))
+        .collect();
+    let mut by_content_id = HashMap::new();
+    for (package_index, package) in packages.iter().enumerate() {
+        for (version_index, version) in package.versions.iter().enumerate() {
+            by_content_id.insert(version.content_id, (package_index, version_index));
+        }
+    }
+
+    let mut paths = HashMap::new();
+    let mut errors = Vec::new();
+    for pending in pending {
+        let mut dependencies = Vec::new();
+
+        for dependency in &pending.dependencies {
+            match resolve_dependency(packages, &by_unique_id, dependency) {
+                Ok(content_id) => {
+                    if !dependencies.contains(&content_id) {
+                        dependencies.push(content_id);
+                    }
+                }
+                Err(msg) => errors.push(Error::Invalid(pending.path.clone(), msg)),
+            }
+        }
+
+        /* ServerInfo sends the amount of dependencies as an u8. */
+        if dependencies.len() > u8::MAX as usize {
+            errors.push(Error::Invalid(
+                pending.path.clone(),
+                format!("more than {} dependencies", u8::MAX),
+            ));
+            dependencies.clear();
+        }
+
+        let (package_index, version_index) = by_content_id[&pending.content_id];
+        packages[package_index].versions[version_index].dependencies = dependencies;
+        paths.insert(pending.content_id, pending.path);
+    }
+
+    (paths, errors)
+}
+
+fn resolve_dependency(
+    packages: &[Package],
+    by_unique_id: &HashMap<(ContentType, u32), usize>,
+    dependency: &DependencyYaml,
+) -> Result<u32, String> {
+    let content_type = content_type_from_folder_name(&dependency.content_type)
+        .ok_or("dependency with invalid content-type")?;
+    let unique_id = parse_hex::<4>(&dependency.unique_id)
+        .map(u32::from_be_bytes)
+        .ok_or("dependency with invalid unique-id")?;
+    let md5sum_partial = parse_hex::<MD5_PARTIAL_SIZE>(&dependency.md5sum_partial)
+        .ok_or("dependency with invalid md5sum-partial")?;
+
+    by_unique_id
+        .get(&(content_type, unique_id))
+        .and_then(|&index| {
+            packages[index]
+                .versions
+                .iter()
+                .find(|version| version.md5[..MD5_PARTIAL_SIZE] == md5sum_partial)
+        })
+        .map(|version| version.content_id)
+        .ok_or_else(|| {
+            format!(
+                "dependency on {}/{}/{} does not exist",
+                dependency.content_type, dependency.unique_id, dependency.md5sum_partial
+            )
+        })
+}
+
+/* A dependency cycle would have a client download packages forever. This is
+ * a depth-first search with its own stack, as dependency chains can be long;
+ * every cycle found is reported. */
+fn check_dependency_cycles(packages: &[Package], paths: &HashMap<u32, PathBuf>) -> Vec<Error> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        Visiting,
+        Done,
+    }
+
+    let dependencies: HashMap<u32, &[u32]> = packages
+        .iter()
+        .flat_map(|package| &package.versions)
+        .map(|version| (version.content_id, version.dependencies.as_slice()))
+        .collect();
+
+    let mut errors = Vec::new();
+    let mut states = HashMap::new();
+    for version in packages.iter().flat_map(|package| &package.versions) {
+        if states.contains_key(&version.content_id) {
+            continue;
+        }
+
+        /* Every entry is a content_id and how many of its dependencies are
+         * visited already. */
+        let mut stack = vec![(version.content_id, 0)];
+        states.insert(version.content_id, State::Visiting);
+
+        while let Some((content_id, visited)) = stack.last_mut() {
+            let content_id = *content_id;
+            let Some(&dependency) = dependencies[&content_id].get(*visited) else {
+                states.insert(content_id, State::Done);
+                stack.pop();
+                continue;
+            };
+            *visited += 1;
+
+            match states.get(&dependency) {
+                Some(State::Done) => (),
+                Some(State::Visiting) => {
+                    let start = stack.iter().position(|&(id, _)| id == dependency).unwrap();
+                    let files: Vec<String> = stack[start..]
+                        .iter()
+                        .map(|(id, _)| id)
+                        .chain([&dependency])
+                        .map(|id| paths[id].display().to_string())
+                        .collect();
+                    errors.push(Error::Invalid(
+                        paths[&dependency].clone(),
+                        format!("dependency cycle: {}", files.join(" -> ")),
+                    ));
+                }
+                None => {
+                    states.insert(dependency, State::Visiting);
+                    stack.push((dependency, 0));
+                }
+            }
+        }
+    }
+
+    errors
+}
+
+/* The name BaNaNaS uses for a content type, in folders and references. */
+fn folder_name(content_type: ContentType) -> &'static str {
+    match content_type {
+        ContentType::BaseGraphics => "base-graphics",
+        ContentType::NewGRF => "newgrf",
+        ContentType::AI => "ai",
+        ContentType::AILibrary => "ai-library",
+        ContentType::Scenario => "scenario",
+        ContentType::Heightmap => "heightmap",
+        ContentType::BaseSounds => "base-sounds",
+        ContentType::BaseMusic => "base-music",
+        ContentType::Game => "game-script",
+        ContentType::GameLibrary => "game-script-library",
+    }
+}
+
+fn content_type_from_folder_name(name: &str) -> Option<ContentType> {
+    ContentType::ALL
+        .into_iter()
+        .find(|&content_type| folder_name(content_type) == name)
+}
+
+fn read_yaml<T: DeserializeOwned>(path: &Path) -> Result<T, Error> {
+    let data = fs::read(path).map_err(|e| Error::Io(path.to_path_buf(), e))?;
+    serde_yaml::from_slice(&data).map_err(|e| Error::Yaml(path.to_path_buf(), e))
+}
+
+/* Folder entries, sorted so loading the same index twice gives the same result. */
+fn sorted_entries(folder: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut entries = fs::read_dir(folder)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|e| Error::Io(folder.to_path_buf(), e))?;
+    entries.sort();
+    Ok(entries)
+}
+
+fn parse_hex<const N: usize>(s: &str) -> Option<[u8; N]> {
+    if s.len() != N * 2 || !s.is_ascii() {
+        return None;
+    }
+
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* A package with one version per md5, numbered from "first_content_id". */
+    fn package(unique_id: u32, first_content_id: u32, md5s: &[[u8; 16]]) -> Package {
+        Package {
+            content_type: ContentType::NewGRF,
+            unique_id,
+            name: format!("package {}", unique_id),
+            url: String::new(),
+            description: String::new(),
+            tags: Vec::new(),
+            versions: md5s
+                .iter()
+                .enumerate()
+                .map(|(index, md5)| Version {
+                    content_id: first_content_id + index as u32,
+                    version: index.to_string(),
+                    upload_date: String::new(),
+                    availability: Availability::NewGames,
+                    md5: *md5,
+                    filesize: 0,
+                    dependencies: Vec::new(),
+                    compatibility: Vec::new(),
+                })
+                .collect(),
+        }
+    }
+
+    fn paths(count: u32) -> HashMap<u32, PathBuf> {
+        (0..count)
+            .map(|content_id| (content_id, PathBuf::from(format!("{}.yaml", content_id))))
+            .collect()
+    }
+
+    fn depend(packages: &mut [Package], content_id: u32, dependencies: &[u32]) {
+        let version = packages
+            .iter_mut()
+            .flat_map(|package| &mut package.versions)
+            .find(|version| version.content_id == content_id)
+            .unwrap();
+        version.dependencies = dependencies.to_vec();
+    }
+
+    #[test]
+    fn missing_dependencies_are_all_reported() {
+        let mut packages = vec![package(1, 0, &[[1; 16]]), package(2, 1, &[[2; 16]])];
+        let dependency = |unique_id: &str, md5sum_partial: &str| DependencyYaml {
+            content_type: "newgrf".to_string(),
+            unique_id: unique_id.to_string(),
+            md5sum_partial: md5sum_partial.to_string(),
+        };
+        let pending = vec![
+            PendingDependencies {
+                content_id: 0,
+                path: PathBuf::from("0.yaml"),
+                dependencies: vec![
+                    dependency("00000002", "02020202"),
+                    dependency("00000003", "03030303"),
+                ],
+            },
+            PendingDependencies {
+                content_id: 1,
+                path: PathBuf::from("1.yaml"),
+                dependencies: vec![dependency("00000001", "ffffffff")],
+            },
+        ];
+
+        let (paths, errors) = resolve_dependencies(&mut packages, pending);
+        assert_eq!(paths.len(), 2);
+        assert_eq!(packages[0].versions[0].dependencies, vec![1]);
+
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "0.yaml: dependency on newgrf/00000003/03030303 does not exist",
+                "1.yaml: dependency on newgrf/00000001/ffffffff does not exist",
+            ]
+        );
+    }
+
+    #[test]
+    fn self_dependency_is_a_cycle() {
+        let mut packages = vec![package(1, 0, &[[1; 16]])];
+        depend(&mut packages, 0, &[0]);
+
+        let errors = check_dependency_cycles(&packages, &paths(1));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "0.yaml: dependency cycle: 0.yaml -> 0.yaml"
+        );
+    }
+
+    #[test]
+    fn cycle_of_three() {
+        let mut packages = vec![
+            package(1, 0, &[[1; 16]]),
+            package(2, 1, &[[2; 16]]),
+            package(3, 2, &[[3; 16]]),
+            package(4, 3, &[[4; 16]]),
+        ];
+        /* 3 only leads into the cycle; it is not part of it. */
+        depend(&mut packages, 0, &[1]);
+        depend(&mut packages, 1, &[2]);
+        depend(&mut packages, 2, &[0]);
+        depend(&mut packages, 3, &[0]);
+
+        let errors = check_dependency_cycles(&packages, &paths(4));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "0.yaml: dependency cycle: 0.yaml -> 1.yaml -> 2.yaml -> 0.yaml"
+        );
+    }
+
+    #[test]
+    fn no_cycle_in_shared_dependencies() {
+        let mut packages = vec![
+            package(1, 0, &[[1; 16]]),
+            package(2, 1, &[[2; 16]]),
+            package(3, 2, &[[3; 16]]),
+        ];
+        depend(&mut packages, 0, &[1, 2]);
+        depend(&mut packages, 1, &[2]);
+
+        assert!(check_dependency_cycles(&packages, &paths(3)).is_empty());
+    }
+}
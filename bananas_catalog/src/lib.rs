@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+pub mod compatibility;
+pub mod index;
+
+/* These are part of the protocol; the catalog only stores them. */
+pub use openttd_protocol::version;
+pub use openttd_protocol::ContentType;
+
+use compatibility::Compatibility;
+use version::BranchVersion;
+
+/* BaNaNaS' "md5sum-partial" is the first few bytes of the md5sum. */
+pub const MD5_PARTIAL_SIZE: usize = 4;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Availability {
+    /* Offered to everyone. */
+    NewGames,
+    /* Only to be found by clients loading a savegame that references it. */
+    SavegamesOnly,
+}
+
+/* A single published version of a package; this is what OpenTTD calls a
+ * content entry, and it is what a content_id points to. */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Version {
+    /* Handed out while loading; only valid for the Catalog it came from. */
+    pub content_id: u32,
+    pub version: String,
+    pub upload_date: String,
+    pub availability: Availability,
+    pub md5: [u8; 16],
+    pub filesize: u32,
+    pub dependencies: Vec<u32>,
+    pub compatibility: Vec<Compatibility>,
+}
+
+impl Version {
+    /* Whether a client running the given (branch, version) pairs can use
+     * this version. */
+    pub fn is_compatible<'a, I>(&self, branches: I) -> bool
+    where
+        I: IntoIterator<Item = (&'a str, &'a BranchVersion)>,
+    {
+        compatibility::is_compatible(&self.compatibility, branches)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Package {
+    pub content_type: ContentType,
+    pub unique_id: u32,
+    pub name: String,
+    pub url: String,
+    pub description: String,
+    pub tags: Vec<String>,
+    pub versions: Vec<Version>,
+}
+
+impl Package {
+    /* A copy of this package with only the given versions. */
+    pub fn with_versions<'a, I>(&self, versions: I) -> Package
+    where
+        I: IntoIterator<Item = &'a Version>,
+    {
+        Package {
+            content_type: self.content_type,
+            unique_id: self.unique_id,
+            name: self.name.clone(),
+            url: self.url.clone(),
+            description: self.description.clone(),
+            tags: self.tags.clone(),
+            versions: versions.into_iter().cloned().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Catalog {
+    packages: Vec<Package>,
+    /* Lookup table from content_id to (package, version) index. */
+    by_content_id: HashMap<u32, (usize, usize)>,
+    by_unique_id: HashMap<(ContentType, u32), usize>,
+}
+
+impl Catalog {
+    pub fn new(packages: Vec<Package>) -> Self {
+        let mut by_content_id = HashMap::new();
+        let mut by_unique_id = HashMap::new();
+        for (package_index, package) in packages.iter().enumerate() {
+            by_unique_id.insert((package.content_type, package.unique_id), package_index);
+            for (version_index, version) in package.versions.iter().enumerate() {
+                by_content_id.insert(version.content_id, (package_index, version_index));
+            }
+        }
+
+        Catalog {
+            packages,
+            by_content_id,
+            by_unique_id,
+        }
+    }
+
+    pub fn packages(&self) -> &[Package] {
+        &self.packages
+    }
+
+    /* The packages a listing of this content type shows, each with only the
+     * versions it shows. Savegame-only versions are never listed, nor are
+     * packages that only have those. */
+    pub fn by_content_type(&self, content_type: ContentType) -> Vec<Package> {
+        self.packages
+            .iter()
+            .filter(|package| package.content_type == content_type)
+            .map(|package| {
+                package.with_versions(
+                    package
+                        .versions
+                        .iter()
+                        .filter(|version| version.availability == Availability::NewGames),
+                )
+            })
+            .filter(|package| !package.versions.is_empty())
+            .collect()
+    }
+
+    pub fn by_content_id(&self, content_id: u32) -> Option<(&Package, &Version)> {
+        self.by_content_id
+            .get(&content_id)
+            .map(|&(package_index, version_index)| {
+                let package = &self.packages[package_index];
+                (package, &package.versions[version_index])
+            })
+    }
+
+    pub fn by_unique_id(&self, content_type: ContentType, unique_id: u32) -> Option<&Package> {
+        self.by_unique_id
+            .get(&(content_type, unique_id))
+            .map(|&package_index| &self.packages[package_index])
+    }
+
+    /* Find the exact version with this md5sum. The md5sum-partial of the
+     * index is only used to find the file; clients always send the full md5sum
+     * and expect exactly that version back. */
+    pub fn by_md5(
+        &self,
+        content_type: ContentType,
+        unique_id: u32,
+        md5: &[u8; 16],
+    ) -> Option<(&Package, &Version)> {
+        let package = self.by_unique_id(content_type, unique_id)?;
+
+        package
+            .versions
+            .iter()
+            .find(|version| version.md5 == *md5)
+            .map(|version| (package, version))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(unique_id: u32, availabilities: &[Availability]) -> Package {
+        Package {
+            content_type: ContentType::NewGRF,
+            unique_id,
+            name: format!("package {}", unique_id),
+            url: String::new(),
+            description: String::new(),
+            tags: Vec::new(),
+            versions: availabilities
+                .iter()
+                .enumerate()
+                .map(|(index, &availability)| Version {
+                    content_id: unique_id * 10 + index as u32,
+                    version: index.to_string(),
+                    upload_date: String::new(),
+                    availability,
+                    md5: [0; 16],
+                    filesize: 0,
+                    dependencies: Vec::new(),
+                    compatibility: Vec::new(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn by_content_type_leaves_out_savegame_only_versions() {
+        let catalog = Catalog::new(vec![
+            package(1, &[Availability::SavegamesOnly, Availability::NewGames]),
+            package(2, &[Availability::SavegamesOnly]),
+        ]);
+
+        let packages = catalog.by_content_type(ContentType::NewGRF);
+        assert_eq!(packages.len(), 1);
+        assert_eq!(packages[0].unique_id, 1);
+        assert_eq!(packages[0].versions.len(), 1);
+        assert_eq!(packages[0].versions[0].content_id, 11);
+
+        assert!(catalog.by_content_type(ContentType::AI).is_empty());
+
+        /* They can still be found by id. */
+        assert!(catalog.by_content_id(20).is_some());
+    }
+}
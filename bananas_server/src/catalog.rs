@@ -0,0 +1,61 @@
+use bananas_catalog::{ContentType, Package, Version};
+
+use super::host::*;
+use super::Error;
+
+/* The catalog lives in the host and is shared between all connections. Every
+ * lookup returns the matching packages, each with only the matching versions. */
+
+fn lookup<F>(query: F) -> Result<Vec<Package>, Error>
+where
+    F: Fn(&mut [u8], i32) -> Result<i32, i32>,
+{
+    /* The host tells us how big the result is; if it doesn't fit, try again
+     * with a buffer that does. */
+    let mut buf = vec![0; 4096];
+    loop {
+        let size = buf.len() as i32;
+        let len = query(&mut buf, size).map_err(|_| Error::CatalogLookupFailure)? as usize;
+        if len <= buf.len() {
+            return bincode::deserialize(&buf[..len]).map_err(Error::CatalogDeserializeFailure);
+        }
+        buf.resize(len, 0);
+    }
+}
+
+/* For lookups of a single version: split it from its package. */
+fn single(packages: Vec<Package>) -> Option<(Package, Version)> {
+    let mut package = packages.into_iter().next()?;
+    let version = package.versions.pop()?;
+    Some((package, version))
+}
+
+/* Only the versions a listing can show: savegame-only content is never
+ * listed, only found by id. */
+pub fn by_content_type(content_type: ContentType) -> Result<Vec<Package>, Error> {
+    lookup(|buf, len| catalog_by_content_type(content_type as u8, buf, len))
+}
+
+pub fn by_content_id(content_id: u32) -> Result<Option<(Package, Version)>, Error> {
+    Ok(single(lookup(|buf, len| {
+        catalog_by_content_id(content_id, buf, len)
+    })?))
+}
+
+pub fn by_unique_id(content_type: ContentType, unique_id: u32) -> Result<Option<Package>, Error> {
+    Ok(
+        lookup(|buf, len| catalog_by_unique_id(content_type as u8, unique_id, buf, len))?
+            .into_iter()
+            .next(),
+    )
+}
+
+pub fn by_md5(
+    content_type: ContentType,
+    unique_id: u32,
+    md5: &[u8; 16],
+) -> Result<Option<(Package, Version)>, Error> {
+    Ok(single(lookup(|buf, len| {
+        catalog_by_md5(content_type as u8, unique_id, md5, buf, len)
+    })?))
+}
@@ -3,10 +3,31 @@ extern "C" {
     fn console_log_ptr(ptr: *const u8, len: i32);
 
     #[link_name = "read"]
-    fn read_ptr(ptr: *const u8, len: i32) -> i32;
+    fn read_ptr(ptr: *mut u8, len: i32, in_packet: i32) -> i32;
 
     #[link_name = "write"]
     fn write_ptr(ptr: *const u8, len: i32) -> i32;
+
+    #[link_name = "catalog_by_content_type"]
+    fn catalog_by_content_type_ptr(content_type: u8, ptr: *mut u8, len: i32) -> i32;
+
+    #[link_name = "catalog_by_content_id"]
+    fn catalog_by_content_id_ptr(content_id: u32, ptr: *mut u8, len: i32) -> i32;
+
+    #[link_name = "catalog_by_unique_id"]
+    fn catalog_by_unique_id_ptr(content_type: u8, unique_id: u32, ptr: *mut u8, len: i32) -> i32;
+
+    #[link_name = "catalog_by_md5"]
+    fn catalog_by_md5_ptr(
+        content_type: u8,
+        unique_id: u32,
+        md5: *const u8,
+        ptr: *mut u8,
+        len: i32,
+    ) -> i32;
+
+    #[link_name = "send_file"]
+    fn send_file_ptr(content_id: u32, offset: i32, len: i32) -> i32;
 }
 
 pub fn console_log(text: &str) {
@@ -15,8 +36,13 @@ pub fn console_log(text: &str) {
     }
 }
 
-pub fn read(buf: &[u8], len: i32) -> Result<i32, i32> {
-    let res = unsafe { read_ptr(buf.as_ptr(), len) };
+/* Why "read" failed, other than a plain read error (-1). */
+pub const READ_IDLE_TIMEOUT: i32 = -2;
+pub const READ_SESSION_EXPIRED: i32 = -3;
+pub const READ_TOO_SLOW: i32 = -4;
+
+pub fn read(buf: &mut [u8], len: i32, in_packet: bool) -> Result<i32, i32> {
+    let res = unsafe { read_ptr(buf.as_mut_ptr(), len, in_packet as i32) };
     if res < 0 {
         Err(res)
     } else {
@@ -33,6 +59,63 @@ pub fn write(buf: &[u8], len: i32) -> Result<i32, i32> {
     }
 }
 
+pub fn catalog_by_content_type(content_type: u8, buf: &mut [u8], len: i32) -> Result<i32, i32> {
+    let res = unsafe { catalog_by_content_type_ptr(content_type, buf.as_mut_ptr(), len) };
+    if res < 0 {
+        Err(res)
+    } else {
+        Ok(res)
+    }
+}
+
+pub fn catalog_by_content_id(content_id: u32, buf: &mut [u8], len: i32) -> Result<i32, i32> {
+    let res = unsafe { catalog_by_content_id_ptr(content_id, buf.as_mut_ptr(), len) };
+    if res < 0 {
+        Err(res)
+    } else {
+        Ok(res)
+    }
+}
+
+pub fn catalog_by_unique_id(
+    content_type: u8,
+    unique_id: u32,
+    buf: &mut [u8],
+    len: i32,
+) -> Result<i32, i32> {
+    let res = unsafe { catalog_by_unique_id_ptr(content_type, unique_id, buf.as_mut_ptr(), len) };
+    if res < 0 {
+        Err(res)
+    } else {
+        Ok(res)
+    }
+}
+
+pub fn catalog_by_md5(
+    content_type: u8,
+    unique_id: u32,
+    md5: &[u8; 16],
+    buf: &mut [u8],
+    len: i32,
+) -> Result<i32, i32> {
+    let res =
+        unsafe { catalog_by_md5_ptr(content_type, unique_id, md5.as_ptr(), buf.as_mut_ptr(), len) };
+    if res < 0 {
+        Err(res)
+    } else {
+        Ok(res)
+    }
+}
+
+pub fn send_file(content_id: u32, offset: i32, len: i32) -> Result<i32, i32> {
+    let res = unsafe { send_file_ptr(content_id, offset, len) };
+    if res < 0 {
+        Err(res)
+    } else {
+        Ok(res)
+    }
+}
+
 macro_rules! console_log {
     ($($t:tt)*) => {
         console_log(&format_args!($($t)*).to_string().as_str())
@@ -1,86 +1,239 @@
-use byteorder::{LittleEndian, ReadBytesExt};
+use bananas_catalog::compatibility::MAIN_BRANCH;
+use bananas_catalog::version::BranchVersion;
+use bananas_catalog::{Availability, Package, Version};
+use openttd_protocol as protocol;
+use openttd_protocol::wire;
 
 #[macro_use]
 mod host;
 use host::*;
 
-mod protocol;
-mod wire;
+mod catalog;
+mod reader;
+mod writer;
 
+#[allow(dead_code)]
 #[derive(Debug)]
 enum Error {
     ConnectionClosed,
     ReadFailure,
+    IdleTimeout,
+    SessionExpired,
+    ReadTooSlow,
     WriteFailure,
     PacketTooSmall,
+    PacketTooBig,
     PacketDeserializeFailure(wire::Error),
     PacketSerializeFailure(wire::Error),
+    CatalogLookupFailure,
+    CatalogDeserializeFailure(bincode::Error),
+    ContentSendFailure(u32),
 }
 
-fn read_packet() -> Result<(), Error> {
-    /* Read the length of the packet. */
-    let buf = vec![0; 2];
-    let res = read(&buf, 2).map_err(|_| Error::ReadFailure)?;
-    if res == 0 {
-        return Err(Error::ConnectionClosed);
-    }
+/* State that lives as long as the connection does. */
+#[derive(Default)]
+struct Session {
+    /* The branches of the last ClientInfoList; None until one is received. */
+    branches: Option<Vec<(String, BranchVersion)>>,
+}
 
-    /* Ensure it is within sane bounds. */
-    let mut buf = &buf[..];
-    let len = buf
-        .read_u16::<LittleEndian>()
-        .map_err(|_| Error::PacketTooSmall)?;
-    if len < 2 {
-        return Err(Error::PacketTooSmall);
+impl Session {
+    fn is_compatible(&self, version: &Version) -> bool {
+        match &self.branches {
+            Some(branches) => version.is_compatible(
+                branches
+                    .iter()
+                    .map(|(branch, version)| (branch.as_str(), version)),
+            ),
+            None => true,
+        }
     }
+}
 
-    /* Read the rest of the packet. */
-    let buf = vec![0; (len - 2) as usize];
-    read(&buf, (len - 2) as i32).map_err(|_| Error::ReadFailure)?;
-    if res == 0 {
-        return Err(Error::ConnectionClosed);
-    }
+fn read_packet(
+    reader: &mut reader::PacketReader,
+    writer: &mut writer::PacketWriter,
+    session: &mut Session,
+) -> Result<(), Error> {
+    let buf = reader.next_packet()?;
 
-    /* Validate and convert the packet to a struct. */
-    let packet = protocol::read_packet(&buf).map_err(|e| Error::PacketDeserializeFailure(e))?;
+    /* Validate and convert the packet to a struct. Newer clients can add
+     * fields to a packet; those are ignored. */
+    let options = wire::DecodeOptions {
+        ignore_trailing: true,
+        ..protocol::CLIENT_DECODE_OPTIONS
+    };
+    let packet = protocol::read_packet_with_options(&buf, options)
+        .map_err(Error::PacketDeserializeFailure)?;
 
     match packet {
         protocol::ClientPacket::ClientInfoList {
             content_type,
-            openttd_version: _,
-            branches: _,
+            openttd_version,
+            branches,
         } => {
-            let packet = protocol::ServerInfo {
-                content_type: content_type,
-                content_id: 0,
-                filesize: 1,
-                name: "test".to_string(),
-                version: "1.0".to_string(),
-                url: "".to_string(),
-                description: "description".to_string(),
-                unique_id: 123,
-                md5: [0; 16],
-                dependencies: vec![].into(),
-                tags: vec!["test".to_string(), "bla".to_string()].into(),
-            };
-
-            let buf = wire::to_bytes(&packet).map_err(|e| Error::PacketSerializeFailure(e))?;
-            write(&buf, buf.len() as i32).map_err(|_| Error::WriteFailure)?;
+            session.branches = Some(match branches {
+                /* Branches with a version we cannot parse can't match anything. */
+                Some(branches) => branches
+                    .into_iter()
+                    .filter_map(|branch| {
+                        Some((branch.branch.into_owned(), branch.version.parse().ok()?))
+                    })
+                    .collect(),
+                /* Older clients only tell us their version of the main branch. */
+                None => openttd_version
+                    .branch_version()
+                    .map(|version| (MAIN_BRANCH.to_string(), version))
+                    .into_iter()
+                    .collect(),
+            });
+
+            for package in catalog::by_content_type(content_type)? {
+                for version in &package.versions {
+                    if !session.is_compatible(version) {
+                        continue;
+                    }
+                    writer.send(&server_info(&package, version))?;
+                }
+            }
+        }
+        protocol::ClientPacket::ClientInfoId { content_infos } => {
+            /* Unknown content_ids are silently skipped; OpenTTD expects that. */
+            for content_info in content_infos.iter() {
+                if let Some((package, version)) = catalog::by_content_id(content_info.content_id)? {
+                    writer.send(&server_info(&package, &version))?;
+                }
+            }
+        }
+        protocol::ClientPacket::ClientInfoExtId { content_infos } => {
+            for content_info in content_infos.iter() {
+                let package =
+                    match catalog::by_unique_id(content_info.content_type, content_info.unique_id)?
+                    {
+                        Some(package) => package,
+                        None => continue,
+                    };
+
+                if let Some(version) = newest_version(&package, session) {
+                    writer.send(&server_info(&package, version))?;
+                }
+            }
+        }
+        protocol::ClientPacket::ClientInfoExtIdMd5 { content_infos } => {
+            /* This asks for one specific version, so no compatibility filter. */
+            for content_info in content_infos.iter() {
+                if let Some((package, version)) = catalog::by_md5(
+                    content_info.content_type,
+                    content_info.unique_id,
+                    &content_info.md5,
+                )? {
+                    writer.send(&server_info(&package, &version))?;
+                }
+            }
+        }
+        protocol::ClientPacket::ClientContent { content_infos } => {
+            for content_info in content_infos.iter() {
+                /* Only content in the catalog can be downloaded; skip anything else. */
+                if let Some((package, version)) = catalog::by_content_id(content_info.content_id)? {
+                    send_content(writer, &package, &version)?;
+                }
+            }
         }
-        _ => (),
     };
 
+    /* Everything this packet asked for is answered; send it out. */
+    writer.flush()
+}
+
+/* The most recently uploaded version the client can use. Savegame-only
+ * versions are only handed out when asked for by md5sum. */
+fn newest_version<'a>(package: &'a Package, session: &Session) -> Option<&'a Version> {
+    package
+        .versions
+        .iter()
+        .filter(|version| version.availability == Availability::NewGames)
+        .filter(|version| session.is_compatible(version))
+        .max_by(|a, b| a.upload_date.cmp(&b.upload_date))
+}
+
+fn server_info(package: &Package, version: &Version) -> protocol::ServerInfo {
+    protocol::ServerInfo {
+        content_type: package.content_type,
+        content_id: version.content_id,
+        filesize: version.filesize,
+        name: protocol::truncate_string(&package.name, protocol::CONTENT_NAME_LENGTH),
+        version: protocol::truncate_string(&version.version, protocol::CONTENT_VERSION_LENGTH),
+        url: protocol::truncate_string(&package.url, protocol::CONTENT_URL_LENGTH),
+        description: protocol::truncate_string(&package.description, protocol::CONTENT_DESC_LENGTH),
+        unique_id: package.unique_id,
+        md5: version.md5,
+        dependencies: version.dependencies.clone(),
+        tags: package
+            .tags
+            .iter()
+            .map(|tag| protocol::truncate_string(tag, protocol::CONTENT_TAG_LENGTH))
+            .collect(),
+    }
+}
+
+/* OpenTTD stores the download as "<filename>.tar"; keep it to safe characters. */
+fn content_filename(name: &str, version: &str) -> String {
+    format!("{}-{}", name, version)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn send_content(
+    writer: &mut writer::PacketWriter,
+    package: &Package,
+    version: &Version,
+) -> Result<(), Error> {
+    let content_id = version.content_id;
+    let filesize = version.filesize as i32;
+
+    writer.send(&protocol::ServerContent {
+        content_type: package.content_type,
+        content_id,
+        filesize: version.filesize,
+        filename: protocol::truncate_string(
+            &content_filename(&package.name, &version.version),
+            protocol::CONTENT_FILENAME_LENGTH,
+        ),
+    })?;
+
+    /* The host streams the file to the client itself, split over as many
+     * ServerContent packets as needed and ended by an empty one; everything
+     * before it has to go first. */
+    writer.flush()?;
+    let sent =
+        send_file(content_id, 0, filesize).map_err(|_| Error::ContentSendFailure(content_id))?;
+    if sent != filesize {
+        return Err(Error::ContentSendFailure(content_id));
+    }
+
     Ok(())
 }
 
 #[no_mangle]
 pub extern "C" fn connect() {
+    let mut reader = reader::PacketReader::default();
+    let mut writer = writer::PacketWriter::default();
+    let mut session = Session::default();
+
     loop {
-        match read_packet() {
+        match read_packet(&mut reader, &mut writer, &mut session) {
             Ok(()) => (),
             Err(e) => {
                 match e {
                     Error::ConnectionClosed => (),
+                    Error::PacketDeserializeFailure(e) => console_log!("Invalid packet: {}", e),
                     _ => console_log!("Connection error: {:?}", e),
                 };
                 break;
@@ -0,0 +1,54 @@
+use openttd_protocol::PacketBuffer;
+
+use super::host::{read, READ_IDLE_TIMEOUT, READ_SESSION_EXPIRED, READ_TOO_SLOW};
+use super::wire;
+use super::Error;
+
+/* How much to ask the host for at once. */
+const READ_SIZE: usize = 4096;
+
+/* Splits the incoming byte stream into packets. The host returns whatever
+ * it has available, which can be part of a packet or several packets. */
+#[derive(Default)]
+pub struct PacketReader {
+    buffer: PacketBuffer,
+}
+
+impl PacketReader {
+    /* Returns the next complete packet, without its length but starting
+     * with its type. */
+    pub fn next_packet(&mut self) -> Result<Vec<u8>, Error> {
+        loop {
+            let packet = self.buffer.take_packet().map_err(|e| match e {
+                wire::Error::PacketTooShort => Error::PacketTooSmall,
+                _ => Error::PacketTooBig,
+            })?;
+            if let Some(packet) = packet {
+                return Ok(packet);
+            }
+            self.fill()?;
+        }
+    }
+
+    fn fill(&mut self) -> Result<(), Error> {
+        let mut buf = vec![0; READ_SIZE];
+        /* Anything in the buffer is part of a packet still coming in. */
+        let res =
+            read(&mut buf, READ_SIZE as i32, !self.buffer.is_empty()).map_err(|res| match res {
+                READ_IDLE_TIMEOUT => Error::IdleTimeout,
+                READ_SESSION_EXPIRED => Error::SessionExpired,
+                READ_TOO_SLOW => Error::ReadTooSlow,
+                _ => Error::ReadFailure,
+            })?;
+        if res == 0 {
+            /* Closing the connection halfway a packet is not a clean close. */
+            if self.buffer.is_empty() {
+                return Err(Error::ConnectionClosed);
+            }
+            return Err(Error::ReadFailure);
+        }
+
+        self.buffer.extend(&buf[..res as usize]);
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+use openttd_protocol::wire;
+
+use super::host::write;
+use super::Error;
+
+/* Send once this much is waiting; a single packet can take it over. */
+const BATCH_SIZE: usize = 32 * 1024;
+
+/* Collects outgoing packets, so answering with many packets doesn't need a
+ * host call for each of them. */
+#[derive(Default)]
+pub struct PacketWriter {
+    buffer: Vec<u8>,
+}
+
+impl PacketWriter {
+    pub fn send<T>(&mut self, packet: &T) -> Result<(), Error>
+    where
+        T: serde::Serialize + wire::Packet,
+    {
+        wire::append_to(&mut self.buffer, packet).map_err(Error::PacketSerializeFailure)?;
+        if self.buffer.len() >= BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let len = self.buffer.len() as i32;
+        let res = write(&self.buffer, len).map_err(|_| Error::WriteFailure)?;
+        if res != len {
+            return Err(Error::WriteFailure);
+        }
+
+        self.buffer.clear();
+        Ok(())
+    }
+}
@@ -0,0 +1,519 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{
+    parse_macro_input, Attribute, Data, DeriveInput, Expr, Fields, GenericParam, Generics, Ident,
+    Lifetime, LitInt, Type, Variant,
+};
+
+/* Derives serde's Serialize and Deserialize for OpenTTD packets and the
+ * structs inside them, encoding every field in order without any framing.
+ *
+ * - "#[packet(id = N)]" on a struct implements wire::Packet with type N.
+ *   On the variants of an enum, it is the type byte that selects the variant;
+ *   a variant either has named fields or wraps a single packet struct.
+ * - "#[len_prefix(L)]" on a Vec<T> field sends the amount of items as L
+ *   before the items themselves.
+ * - An Option<T> field can only be the last field; it is None when the
+ *   packet ends before it, and None is sent by ending the packet early.
+ *   With "#[present_if(expr)]", expr decides instead, so bytes a newer
+ *   version added after it are not mistaken for it; expr can use the
+ *   fields before it.
+ * - Lifetimes are allowed, so fields like &'a str can borrow from the
+ *   packet being read. A Cow<'a, str> field borrows too, unless the
+ *   StringPolicy changed the string. */
+#[proc_macro_derive(OpenttdPacket, attributes(packet, len_prefix, present_if))]
+pub fn derive_openttd_packet(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+struct Field {
+    ident: Ident,
+    ty: Type,
+    len_prefix: Option<Type>,
+    optional: bool,
+    present_if: Option<Expr>,
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream> {
+    match &input.data {
+        Data::Struct(data) => expand_struct(input, &parse_fields(&data.fields)?),
+        Data::Enum(data) => expand_enum(input, &data.variants.iter().collect::<Vec<_>>()),
+        Data::Union(_) => Err(syn::Error::new_spanned(
+            input,
+            "OpenttdPacket cannot be derived for unions",
+        )),
+    }
+}
+
+/* Only lifetimes are supported as generics; anything else would need bounds. */
+fn parse_lifetimes(generics: &Generics) -> syn::Result<Vec<Lifetime>> {
+    generics
+        .params
+        .iter()
+        .map(|param| match param {
+            GenericParam::Lifetime(param) => Ok(param.lifetime.clone()),
+            _ => Err(syn::Error::new_spanned(
+                param,
+                "OpenttdPacket only supports lifetime generics",
+            )),
+        })
+        .collect()
+}
+
+fn parse_packet_id(attrs: &[Attribute]) -> syn::Result<Option<u8>> {
+    let mut id = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("packet")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("id") {
+                let lit: LitInt = meta.value()?.parse()?;
+                id = Some(lit.base10_parse::<u8>()?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported packet attribute"))
+            }
+        })?;
+    }
+    Ok(id)
+}
+
+fn parse_fields(fields: &Fields) -> syn::Result<Vec<Field>> {
+    let fields = match fields {
+        Fields::Named(fields) => &fields.named,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                fields,
+                "OpenttdPacket needs named fields",
+            ))
+        }
+    };
+
+    let mut result = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let mut len_prefix = None;
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("len_prefix"))
+        {
+            len_prefix = Some(attr.parse_args::<Type>()?);
+        }
+
+        let mut present_if = None;
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("present_if"))
+        {
+            present_if = Some(attr.parse_args::<Expr>()?);
+        }
+
+        let optional = is_option(&field.ty);
+        if present_if.is_some() && !optional {
+            return Err(syn::Error::new_spanned(
+                field,
+                "only an Option can have #[present_if]",
+            ));
+        }
+        if optional && i != fields.len() - 1 {
+            return Err(syn::Error::new_spanned(
+                field,
+                "only the last field can be an Option",
+            ));
+        }
+
+        result.push(Field {
+            ident: field.ident.clone().unwrap(),
+            ty: field.ty.clone(),
+            len_prefix,
+            optional,
+            present_if,
+        });
+    }
+    Ok(result)
+}
+
+fn is_option(ty: &Type) -> bool {
+    is_named(ty, "Option")
+}
+
+fn is_cow(ty: &Type) -> bool {
+    is_named(ty, "Cow")
+}
+
+fn is_named(ty: &Type, name: &str) -> bool {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == name),
+        _ => false,
+    }
+}
+
+/* Statements that serialize every field into "state"; "value" gives the
+ * expression to read a field with. */
+fn serialize_fields<F>(fields: &[Field], value: F) -> Vec<TokenStream>
+where
+    F: Fn(&Ident) -> TokenStream,
+{
+    fields
+        .iter()
+        .map(|field| {
+            let name = field.ident.to_string();
+            let value = value(&field.ident);
+            match (&field.len_prefix, field.optional) {
+                (Some(len_prefix), false) => quote! {
+                    state.serialize_field(
+                        #name,
+                        &crate::wire::LenPrefixedRef::<#len_prefix, _>::new(#value),
+                    )?;
+                },
+                (Some(len_prefix), true) => quote! {
+                    state.serialize_field(
+                        #name,
+                        &(#value)
+                            .as_ref()
+                            .map(|value| crate::wire::LenPrefixedRef::<#len_prefix, _>::new(value)),
+                    )?;
+                },
+                (None, _) => quote! {
+                    state.serialize_field(#name, #value)?;
+                },
+            }
+        })
+        .collect()
+}
+
+/* A Visitor that reads all fields in order and constructs the value. */
+fn deserialize_visitor(
+    visitor: &Ident,
+    lifetimes: &[Lifetime],
+    value_type: &TokenStream,
+    constructor: &TokenStream,
+    expecting: &str,
+    fields: &[Field],
+) -> TokenStream {
+    let reads = fields.iter().enumerate().map(|(i, field)| {
+        let ident = &field.ident;
+        let ty = &field.ty;
+        let invalid_length = quote! { ::serde::de::Error::invalid_length(#i, &self) };
+        /* With #[present_if], the value itself is read as if not optional. */
+        let until_end = field.optional && field.present_if.is_none();
+        let read = match (&field.len_prefix, until_end) {
+            (Some(len_prefix), false) => quote! {
+                match seq.next_element::<#len_prefix>()? {
+                    Some(len) => seq.next_element_seed(crate::wire::LenPrefixed::new(len)?)?,
+                    None => None,
+                }
+            },
+            (Some(len_prefix), true) => quote! {
+                match seq.next_element::<Option<#len_prefix>>()? {
+                    Some(Some(len)) => seq
+                        .next_element_seed(crate::wire::LenPrefixed::new(len)?)?
+                        .map(Some),
+                    Some(None) => Some(None),
+                    None => None,
+                }
+            },
+            (None, _) if is_cow(ty) => quote! {
+                seq.next_element::<crate::wire::CowStr>()?
+                    .map(crate::wire::CowStr::into_inner)
+            },
+            (None, _) => quote! { seq.next_element()? },
+        };
+
+        match &field.present_if {
+            Some(present_if) => quote! {
+                let #ident: #ty = if #present_if {
+                    Some(#read.ok_or_else(|| #invalid_length)?)
+                } else {
+                    None
+                };
+            },
+            None => quote! {
+                let #ident: #ty = #read.ok_or_else(|| #invalid_length)?;
+            },
+        }
+    });
+    let idents = fields.iter().map(|field| &field.ident);
+
+    quote! {
+        struct #visitor<#(#lifetimes),*>(::std::marker::PhantomData<(#(&#lifetimes ()),*)>);
+
+        impl<'de: #(#lifetimes)+*, #(#lifetimes),*> ::serde::de::Visitor<'de>
+            for #visitor<#(#lifetimes),*>
+        {
+            type Value = #value_type;
+
+            fn expecting(&self, formatter: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                formatter.write_str(#expecting)
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> ::std::result::Result<Self::Value, A::Error>
+            where
+                A: ::serde::de::SeqAccess<'de>,
+            {
+                #(#reads)*
+                Ok(#constructor { #(#idents),* })
+            }
+        }
+    }
+}
+
+/* The name of every element the visitor reads; the length of a
+ * #[len_prefix] field is read separately from its items, under the same name. */
+fn field_names(fields: &[Field]) -> Vec<String> {
+    fields
+        .iter()
+        .flat_map(|field| {
+            let count = if field.len_prefix.is_some() { 2 } else { 1 };
+            std::iter::repeat_n(field.ident.to_string(), count)
+        })
+        .collect()
+}
+
+fn expand_struct(input: &DeriveInput, fields: &[Field]) -> syn::Result<TokenStream> {
+    let name = &input.ident;
+    let name_str = name.to_string();
+    let field_count = fields.len();
+    let field_names = field_names(fields);
+    let lifetimes = parse_lifetimes(&input.generics)?;
+    let (impl_generics, ty_generics, _) = input.generics.split_for_impl();
+
+    let packet = parse_packet_id(&input.attrs)?.map(|id| {
+        quote! {
+            impl #impl_generics crate::wire::Packet for #name #ty_generics {
+                fn packet_type(&self) -> u8 {
+                    #id
+                }
+
+                fn name(packet_type: u8) -> Option<&'static str> {
+                    (packet_type == #id).then_some(#name_str)
+                }
+            }
+        }
+    });
+
+    let serialize = serialize_fields(fields, |ident| quote! { &self.#ident });
+    let visitor = deserialize_visitor(
+        &Ident::new("PacketVisitor", name.span()),
+        &lifetimes,
+        &quote! { #name #ty_generics },
+        &quote! { #name },
+        &format!("struct {}", name_str),
+        fields,
+    );
+
+    Ok(quote! {
+        #packet
+
+        impl #impl_generics ::serde::Serialize for #name #ty_generics {
+            fn serialize<S>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error>
+            where
+                S: ::serde::Serializer,
+            {
+                use ::serde::ser::SerializeStruct;
+
+                let mut state = serializer.serialize_struct(#name_str, #field_count)?;
+                #(#serialize)*
+                state.end()
+            }
+        }
+
+        impl<'de: #(#lifetimes)+*, #(#lifetimes),*> ::serde::Deserialize<'de> for #name #ty_generics {
+            fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+            where
+                D: ::serde::Deserializer<'de>,
+            {
+                #visitor
+
+                const FIELDS: &[&str] = &[#(#field_names),*];
+                deserializer.deserialize_struct(
+                    #name_str,
+                    FIELDS,
+                    PacketVisitor(::std::marker::PhantomData),
+                )
+            }
+        }
+    })
+}
+
+fn check_unique_id(ids: &mut Vec<u8>, variant: &Variant, id: u8) -> syn::Result<()> {
+    if ids.contains(&id) {
+        return Err(syn::Error::new_spanned(
+            variant,
+            "another variant already has this packet id",
+        ));
+    }
+    ids.push(id);
+    Ok(())
+}
+
+fn expand_enum(input: &DeriveInput, variants: &[&Variant]) -> syn::Result<TokenStream> {
+    let name = &input.ident;
+    let name_str = name.to_string();
+    let lifetimes = parse_lifetimes(&input.generics)?;
+    let (impl_generics, ty_generics, _) = input.generics.split_for_impl();
+
+    let mut serialize_arms = Vec::new();
+    let mut deserialize_arms = Vec::new();
+    let mut type_arms = Vec::new();
+    let mut name_arms = Vec::new();
+    let mut variant_names = Vec::new();
+    let mut ids = Vec::new();
+
+    for variant in variants {
+        let ident = &variant.ident;
+        let variant_str = ident.to_string();
+        let id = parse_packet_id(&variant.attrs)?.ok_or_else(|| {
+            syn::Error::new_spanned(variant, "every variant needs a #[packet(id = N)]")
+        })?;
+        check_unique_id(&mut ids, variant, id)?;
+        name_arms.push(quote! { #id => Some(#variant_str), });
+        variant_names.push(variant_str.clone());
+
+        if let Fields::Unnamed(fields) = &variant.fields {
+            if fields.unnamed.len() != 1 {
+                return Err(syn::Error::new_spanned(
+                    fields,
+                    "a variant can only wrap a single packet",
+                ));
+            }
+            let ty = &fields.unnamed[0].ty;
+
+            type_arms.push(quote! { #name::#ident(_) => #id, });
+            serialize_arms.push(quote! {
+                #name::#ident(packet) => serializer.serialize_newtype_variant(
+                    #name_str,
+                    #id as u32,
+                    #variant_str,
+                    packet,
+                ),
+            });
+            deserialize_arms.push(quote! {
+                #id => variant.newtype_variant::<#ty>().map(#name::#ident),
+            });
+            continue;
+        }
+
+        let fields = parse_fields(&variant.fields)?;
+        let field_count = fields.len();
+        let field_names = field_names(&fields);
+        let idents: Vec<&Ident> = fields.iter().map(|field| &field.ident).collect();
+
+        type_arms.push(quote! { #name::#ident { .. } => #id, });
+
+        let serialize = serialize_fields(&fields, |ident| quote! { #ident });
+        serialize_arms.push(quote! {
+            #name::#ident { #(#idents),* } => {
+                let mut state = serializer.serialize_struct_variant(
+                    #name_str,
+                    #id as u32,
+                    #variant_str,
+                    #field_count,
+                )?;
+                #(#serialize)*
+                state.end()
+            }
+        });
+
+        let visitor = deserialize_visitor(
+            &Ident::new("VariantVisitor", ident.span()),
+            &lifetimes,
+            &quote! { #name #ty_generics },
+            &quote! { #name::#ident },
+            &format!("packet {}::{}", name_str, variant_str),
+            &fields,
+        );
+        deserialize_arms.push(quote! {
+            #id => {
+                #visitor
+
+                const FIELDS: &[&str] = &[#(#field_names),*];
+                variant.struct_variant(FIELDS, VariantVisitor(::std::marker::PhantomData))
+            }
+        });
+    }
+
+    Ok(quote! {
+        impl #impl_generics crate::wire::Packet for #name #ty_generics {
+            fn packet_type(&self) -> u8 {
+                match self {
+                    #(#type_arms)*
+                }
+            }
+
+            fn name(packet_type: u8) -> Option<&'static str> {
+                match packet_type {
+                    #(#name_arms)*
+                    _ => None,
+                }
+            }
+        }
+
+        impl #impl_generics ::serde::Serialize for #name #ty_generics {
+            #[allow(unused_imports)]
+            fn serialize<S>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error>
+            where
+                S: ::serde::Serializer,
+            {
+                use ::serde::ser::SerializeStructVariant;
+
+                match self {
+                    #(#serialize_arms)*
+                }
+            }
+        }
+
+        impl<'de: #(#lifetimes)+*, #(#lifetimes),*> ::serde::Deserialize<'de> for #name #ty_generics {
+            fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+            where
+                D: ::serde::Deserializer<'de>,
+            {
+                struct PacketVisitor<#(#lifetimes),*>(::std::marker::PhantomData<(#(&#lifetimes ()),*)>);
+
+                impl<'de: #(#lifetimes)+*, #(#lifetimes),*> ::serde::de::Visitor<'de>
+                    for PacketVisitor<#(#lifetimes),*>
+                {
+                    type Value = #name #ty_generics;
+
+                    fn expecting(
+                        &self,
+                        formatter: &mut ::std::fmt::Formatter,
+                    ) -> ::std::fmt::Result {
+                        formatter.write_str(concat!("packet ", #name_str))
+                    }
+
+                    fn visit_enum<A>(self, data: A) -> ::std::result::Result<Self::Value, A::Error>
+                    where
+                        A: ::serde::de::EnumAccess<'de>,
+                    {
+                        use ::serde::de::VariantAccess;
+
+                        /* The packet type byte selects the variant. */
+                        let (id, variant): (u8, _) = data.variant()?;
+                        match id {
+                            #(#deserialize_arms)*
+                            _ => Err(::serde::de::Error::invalid_value(
+                                ::serde::de::Unexpected::Unsigned(id as u64),
+                                &self,
+                            )),
+                        }
+                    }
+                }
+
+                const VARIANTS: &[&str] = &[#(#variant_names),*];
+                deserializer.deserialize_enum(
+                    #name_str,
+                    VARIANTS,
+                    PacketVisitor(::std::marker::PhantomData),
+                )
+            }
+        }
+    })
+}
@@ -0,0 +1,169 @@
+use std::borrow::Cow;
+
+use openttd_packet_derive::OpenttdPacket;
+use serde_repr::{Deserialize_repr, Serialize_repr};
+
+pub use super::version::OpenttdVersion;
+
+/* The kinds of content OpenTTD can download. */
+#[derive(Serialize_repr, Deserialize_repr, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum ContentType {
+    BaseGraphics = 1,
+    NewGRF = 2,
+    AI = 3,
+    AILibrary = 4,
+    Scenario = 5,
+    Heightmap = 6,
+    BaseSounds = 7,
+    BaseMusic = 8,
+    Game = 9,
+    GameLibrary = 10,
+}
+
+impl ContentType {
+    pub const ALL: [ContentType; 10] = [
+        ContentType::BaseGraphics,
+        ContentType::NewGRF,
+        ContentType::AI,
+        ContentType::AILibrary,
+        ContentType::Scenario,
+        ContentType::Heightmap,
+        ContentType::BaseSounds,
+        ContentType::BaseMusic,
+        ContentType::Game,
+        ContentType::GameLibrary,
+    ];
+}
+
+impl TryFrom<u8> for ContentType {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        ContentType::ALL
+            .into_iter()
+            .find(|content_type| *content_type as u8 == value)
+            .ok_or(value)
+    }
+}
+
+/* These come from the client and are sanitized; they only own their string
+ * when sanitizing changed it. */
+#[derive(OpenttdPacket, Debug)]
+pub struct ClientInfoListBranch<'a> {
+    pub branch: Cow<'a, str>,
+    pub version: Cow<'a, str>,
+}
+
+#[derive(OpenttdPacket, Debug)]
+pub struct ClientInfoIdContentInfo {
+    pub content_id: u32,
+}
+
+#[derive(OpenttdPacket, Debug)]
+pub struct ClientInfoExtIdContentInfo {
+    pub content_type: ContentType,
+    pub unique_id: u32,
+}
+
+#[derive(OpenttdPacket, Debug)]
+pub struct ClientInfoExtIdMd5ContentInfo {
+    pub content_type: ContentType,
+    pub unique_id: u32,
+    pub md5: [u8; 16],
+}
+
+/* The longest strings OpenTTD accepts in content packets, in bytes and
+ * including the nul-terminator. */
+pub const CONTENT_FILENAME_LENGTH: usize = 48;
+pub const CONTENT_NAME_LENGTH: usize = 32;
+pub const CONTENT_VERSION_LENGTH: usize = 16;
+pub const CONTENT_URL_LENGTH: usize = 96;
+pub const CONTENT_DESC_LENGTH: usize = 512;
+pub const CONTENT_TAG_LENGTH: usize = 32;
+/* Any other string a client sends us, like a branch name or version. */
+pub const CLIENT_STRING_LENGTH: usize = 32;
+
+/* Cut a string so it fits in a field of the given length, without cutting
+ * through a UTF-8 sequence. */
+pub fn truncate_string(s: &str, length: usize) -> String {
+    let mut end = std::cmp::min(s.len(), length - 1);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    s[..end].to_string()
+}
+
+#[derive(OpenttdPacket, Debug)]
+#[packet(id = 4)]
+pub struct ServerInfo {
+    pub content_type: ContentType,
+    pub content_id: u32,
+    pub filesize: u32,
+    pub name: String,
+    pub version: String,
+    pub url: String,
+    pub description: String,
+    pub unique_id: u32,
+    pub md5: [u8; 16],
+    #[len_prefix(u8)]
+    pub dependencies: Vec<u32>,
+    #[len_prefix(u8)]
+    pub tags: Vec<String>,
+}
+
+/* The first ServerContent packet of a download is the header; everything
+ * after that is raw file data, in as many packets as needed. Those are
+ * written by the host directly (see host::send_file), and can be read with
+ * read_content_data(). */
+#[derive(OpenttdPacket, Debug)]
+#[packet(id = 6)]
+pub struct ServerContent {
+    pub content_type: ContentType,
+    pub content_id: u32,
+    pub filesize: u32,
+    pub filename: String,
+}
+
+#[derive(OpenttdPacket, Debug)]
+pub enum ClientPacket<'a> {
+    #[packet(id = 0)]
+    ClientInfoList {
+        content_type: ContentType,
+        openttd_version: OpenttdVersion,
+        #[len_prefix(u8)]
+        #[present_if(openttd_version.has_branches())]
+        branches: Option<Vec<ClientInfoListBranch<'a>>>,
+    },
+    #[packet(id = 1)]
+    ClientInfoId {
+        #[len_prefix(u16)]
+        content_infos: Vec<ClientInfoIdContentInfo>,
+    },
+    #[packet(id = 2)]
+    ClientInfoExtId {
+        #[len_prefix(u8)]
+        content_infos: Vec<ClientInfoExtIdContentInfo>,
+    },
+    #[packet(id = 3)]
+    ClientInfoExtIdMd5 {
+        #[len_prefix(u8)]
+        content_infos: Vec<ClientInfoExtIdMd5ContentInfo>,
+    },
+    // 4 is a server-packet
+    #[packet(id = 5)]
+    ClientContent {
+        #[len_prefix(u16)]
+        content_infos: Vec<ClientInfoIdContentInfo>,
+    },
+    // 6 is a server-packet
+}
+
+/* The packets a server sends, for when we are the client. */
+#[derive(OpenttdPacket, Debug)]
+pub enum ServerPacket {
+    #[packet(id = 4)]
+    ServerInfo(ServerInfo),
+    #[packet(id = 6)]
+    ServerContent(ServerContent),
+}
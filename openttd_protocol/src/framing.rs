@@ -0,0 +1,116 @@
+use super::wire::{self, TCP_MTU};
+
+/* The length (u16) and type (u8) every packet starts with. */
+const HEADER_SIZE: usize = 3;
+
+/* Splits an incoming byte stream into packets. Bytes are added as they are
+ * received, which can be part of a packet or several packets at once. */
+#[derive(Default)]
+pub struct PacketBuffer {
+    buffer: Vec<u8>,
+}
+
+impl PacketBuffer {
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /* Whether there is nothing left of a packet still coming in. */
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /* Takes the next complete packet, without its length but starting with
+     * its type, as read_packet() expects it. */
+    pub fn take_packet(&mut self) -> Result<Option<Vec<u8>>, wire::Error> {
+        let len = match packet_length(&self.buffer)? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+
+        let packet = self.buffer[2..len].to_vec();
+        self.buffer.drain(..len);
+        Ok(Some(packet))
+    }
+}
+
+/* The length of the packet at the start of "buffer", if all of it is there. */
+fn packet_length(buffer: &[u8]) -> Result<Option<usize>, wire::Error> {
+    if buffer.len() < 2 {
+        return Ok(None);
+    }
+
+    /* Ensure it is within sane bounds. */
+    let len = u16::from_le_bytes([buffer[0], buffer[1]]) as usize;
+    if len < HEADER_SIZE {
+        return Err(wire::Error::PacketTooShort);
+    }
+    if len > TCP_MTU {
+        return Err(wire::Error::PacketTooLong);
+    }
+
+    if buffer.len() < len {
+        return Ok(None);
+    }
+    Ok(Some(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* What a single read would hand over. */
+    fn buffer_with(data: &[u8]) -> PacketBuffer {
+        PacketBuffer {
+            buffer: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn length_too_small() {
+        assert!(matches!(
+            packet_length(&[2, 0]),
+            Err(wire::Error::PacketTooShort)
+        ));
+        assert!(matches!(
+            packet_length(&[0, 0, 1]),
+            Err(wire::Error::PacketTooShort)
+        ));
+    }
+
+    #[test]
+    fn length_too_big() {
+        let len = (TCP_MTU as u16 + 1).to_le_bytes();
+        assert!(matches!(
+            packet_length(&len),
+            Err(wire::Error::PacketTooLong)
+        ));
+
+        let len = (TCP_MTU as u16).to_le_bytes();
+        assert!(matches!(packet_length(&len), Ok(None)));
+    }
+
+    #[test]
+    fn packet_split_across_reads() {
+        let mut buffer = buffer_with(&[5]);
+        assert!(matches!(buffer.take_packet(), Ok(None)));
+
+        buffer.extend(&[0, 6, 1]);
+        assert!(matches!(buffer.take_packet(), Ok(None)));
+
+        buffer.extend(&[2]);
+        assert_eq!(buffer.take_packet().unwrap(), Some(vec![6, 1, 2]));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn two_packets_in_one_read() {
+        let mut buffer = buffer_with(&[3, 0, 4, 4, 0, 6, 9, 3]);
+        assert_eq!(buffer.take_packet().unwrap(), Some(vec![4]));
+        assert_eq!(buffer.take_packet().unwrap(), Some(vec![6, 9]));
+
+        /* The start of a third packet stays behind. */
+        assert!(matches!(buffer.take_packet(), Ok(None)));
+        assert_eq!(buffer.buffer, vec![3]);
+    }
+}
@@ -0,0 +1,287 @@
+mod content;
+pub use content::*;
+
+mod framing;
+pub use framing::PacketBuffer;
+
+pub mod version;
+pub mod wire;
+
+const SERVER_CONTENT_TYPE: u8 = 6;
+
+/* Clients are not trusted, so their strings are sanitized, and a packet
+ * has to end right after its last field. */
+pub const CLIENT_DECODE_OPTIONS: wire::DecodeOptions = wire::DecodeOptions {
+    strings: wire::StringPolicy::Sanitize {
+        max_length: CLIENT_STRING_LENGTH - 1,
+    },
+    ignore_trailing: false,
+};
+
+pub fn read_packet(buf: &[u8]) -> Result<content::ClientPacket<'_>, wire::Error> {
+    read_packet_with_options(buf, CLIENT_DECODE_OPTIONS)
+}
+
+pub fn read_packet_with_options(
+    buf: &[u8],
+    options: wire::DecodeOptions,
+) -> Result<content::ClientPacket<'_>, wire::Error> {
+    wire::from_bytes_with_options::<content::ClientPacket>(buf, options)
+}
+
+pub fn read_server_packet(buf: &[u8]) -> Result<content::ServerPacket, wire::Error> {
+    wire::from_bytes::<content::ServerPacket>(buf)
+}
+
+/* The data of a ServerContent packet that follows the header. */
+pub fn read_content_data(buf: &[u8]) -> Result<&[u8], wire::Error> {
+    match buf.split_first() {
+        Some((&SERVER_CONTENT_TYPE, data)) => Ok(data),
+        Some(_) => Err(wire::Error::ValidationError(
+            "expected a ServerContent packet".to_string(),
+        )),
+        None => Err(wire::Error::PacketTooShort),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    /* Encode, check the length in the header, and hand back what a reader
+     * would pass to read_packet(): everything after the length. */
+    fn encode<T: serde::Serialize + wire::Packet>(packet: &T) -> Vec<u8> {
+        let bytes = wire::to_bytes(packet).unwrap();
+        assert_eq!(
+            u16::from_le_bytes([bytes[0], bytes[1]]) as usize,
+            bytes.len()
+        );
+        assert_eq!(bytes[2], packet.packet_type());
+        bytes[2..].to_vec()
+    }
+
+    fn round_trip_client(packet: ClientPacket) {
+        let bytes = encode(&packet);
+        let decoded = read_packet(&bytes).unwrap();
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", packet));
+    }
+
+    fn round_trip_server(packet: ServerPacket) {
+        let bytes = encode(&packet);
+        let decoded = read_server_packet(&bytes).unwrap();
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", packet));
+    }
+
+    #[test]
+    fn client_info_list() {
+        round_trip_client(ClientPacket::ClientInfoList {
+            content_type: ContentType::NewGRF,
+            openttd_version: OpenttdVersion(0x1e180000),
+            branches: None,
+        });
+        round_trip_client(ClientPacket::ClientInfoList {
+            content_type: ContentType::AI,
+            openttd_version: OpenttdVersion::BRANCHES,
+            branches: Some(vec![ClientInfoListBranch {
+                branch: "vanilla".into(),
+                version: "14.1".into(),
+            }]),
+        });
+    }
+
+    #[test]
+    fn branches_borrow_unless_sanitized() {
+        let bytes = [
+            0, 2, 0xff, 0xff, 0xff, 0xff, 2, b'v', b'a', b'n', b'i', b'l', b'l', b'a', 0, b'1',
+            b'4', 0, b'x', 0x01, 0, b'1', 0,
+        ];
+        let Ok(ClientPacket::ClientInfoList {
+            branches: Some(branches),
+            ..
+        }) = read_packet(&bytes)
+        else {
+            panic!("expected a ClientInfoList with branches");
+        };
+
+        assert!(matches!(branches[0].branch, Cow::Borrowed("vanilla")));
+        assert!(matches!(branches[0].version, Cow::Borrowed("14")));
+        /* The control character is removed, so this one is a copy. */
+        assert!(matches!(&branches[1].branch, Cow::Owned(branch) if branch == "x"));
+        assert!(matches!(branches[1].version, Cow::Borrowed("1")));
+    }
+
+    #[test]
+    fn client_info_id() {
+        round_trip_client(ClientPacket::ClientInfoId {
+            content_infos: vec![
+                ClientInfoIdContentInfo { content_id: 1 },
+                ClientInfoIdContentInfo { content_id: 70000 },
+            ],
+        });
+    }
+
+    #[test]
+    fn client_info_ext_id() {
+        round_trip_client(ClientPacket::ClientInfoExtId {
+            content_infos: vec![ClientInfoExtIdContentInfo {
+                content_type: ContentType::Game,
+                unique_id: 0x12345678,
+            }],
+        });
+    }
+
+    #[test]
+    fn client_info_ext_id_md5() {
+        round_trip_client(ClientPacket::ClientInfoExtIdMd5 {
+            content_infos: vec![ClientInfoExtIdMd5ContentInfo {
+                content_type: ContentType::Scenario,
+                unique_id: 7,
+                md5: [0xab; 16],
+            }],
+        });
+    }
+
+    #[test]
+    fn client_content() {
+        round_trip_client(ClientPacket::ClientContent {
+            content_infos: vec![ClientInfoIdContentInfo { content_id: 3 }],
+        });
+    }
+
+    #[test]
+    fn server_info() {
+        round_trip_server(ServerPacket::ServerInfo(ServerInfo {
+            content_type: ContentType::NewGRF,
+            content_id: 12,
+            filesize: 1234,
+            name: "OpenGFX+ Trains".to_string(),
+            version: "1.0".to_string(),
+            url: String::new(),
+            description: "Trains".to_string(),
+            unique_id: 0x4f474600,
+            md5: [1; 16],
+            dependencies: vec![3, 4],
+            tags: vec!["train".to_string()],
+        }));
+    }
+
+    #[test]
+    fn truncate_string_at_limit() {
+        /* The field holds 3 bytes and the terminator. */
+        assert_eq!(truncate_string("abc", 4), "abc");
+        assert_eq!(truncate_string("abcd", 4), "abc");
+
+        /* "€" is 3 bytes; the limit falls inside it, so all of it goes. */
+        assert_eq!(truncate_string("ab€", 4), "ab");
+        assert_eq!(truncate_string("a€", 5), "a€");
+        assert_eq!(truncate_string("€€", 4), "€");
+    }
+
+    #[test]
+    fn oversized_packet() {
+        let packet = ServerInfo {
+            content_type: ContentType::NewGRF,
+            content_id: 12,
+            filesize: 1234,
+            name: "OpenGFX+ Trains".to_string(),
+            version: "1.0".to_string(),
+            url: String::new(),
+            description: "x".repeat(wire::TCP_MTU),
+            unique_id: 0x4f474600,
+            md5: [1; 16],
+            dependencies: vec![],
+            tags: vec![],
+        };
+        assert!(matches!(
+            wire::to_bytes(&packet),
+            Err(wire::Error::PacketTooLong)
+        ));
+
+        /* A packet that doesn't fit leaves the ones before it alone. */
+        let mut output = wire::to_bytes(&ServerContent {
+            content_type: ContentType::BaseMusic,
+            content_id: 5,
+            filesize: 99,
+            filename: "music-1.0".to_string(),
+        })
+        .unwrap();
+        let before = output.clone();
+        assert!(matches!(
+            wire::append_to(&mut output, &packet),
+            Err(wire::Error::PacketTooLong)
+        ));
+        assert_eq!(output, before);
+    }
+
+    #[test]
+    fn server_content() {
+        round_trip_server(ServerPacket::ServerContent(ServerContent {
+            content_type: ContentType::BaseMusic,
+            content_id: 5,
+            filesize: 99,
+            filename: "music-1.0".to_string(),
+        }));
+    }
+
+    #[test]
+    fn content_data() {
+        assert_eq!(read_content_data(&[6, 1, 2, 3]).unwrap(), &[1, 2, 3]);
+        assert!(read_content_data(&[4, 1, 2, 3]).is_err());
+        assert!(matches!(
+            read_content_data(&[]),
+            Err(wire::Error::PacketTooShort)
+        ));
+    }
+
+    #[test]
+    fn malformed_packet() {
+        /* ClientInfoId claiming two content_ids, but carrying only one. */
+        let bytes = [1, 2, 0, 1, 0, 0, 0];
+        let error = read_packet(&bytes).unwrap_err().to_string();
+        assert!(error.contains("packet type 1"), "{}", error);
+        assert!(error.contains("content_infos[1]"), "{}", error);
+
+        /* An unknown packet type. */
+        assert!(read_packet(&[4]).is_err());
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        /* ClientInfoId with a single content_id, followed by a byte too many. */
+        let bytes = [1, 1, 0, 7, 0, 0, 0, 42];
+        let error = read_packet(&bytes).unwrap_err().to_string();
+        assert!(error.contains("offset 7"), "{}", error);
+        assert!(error.contains("packet too long"), "{}", error);
+
+        let options = wire::DecodeOptions {
+            ignore_trailing: true,
+            ..CLIENT_DECODE_OPTIONS
+        };
+        assert!(read_packet_with_options(&bytes, options).is_ok());
+    }
+
+    #[test]
+    fn branches_follow_openttd_version() {
+        let options = wire::DecodeOptions {
+            ignore_trailing: true,
+            ..CLIENT_DECODE_OPTIONS
+        };
+
+        /* A ClientInfoList for NewGRFs from OpenTTD 14.0, followed by bytes a
+         * newer version could add; those must not be taken for branches. */
+        let mut bytes = vec![0, 2];
+        bytes.extend_from_slice(&0x1e180000u32.to_le_bytes());
+        bytes.extend_from_slice(&[1, 0]);
+        assert!(read_packet(&bytes).is_err());
+        assert!(matches!(
+            read_packet_with_options(&bytes, options),
+            Ok(ClientPacket::ClientInfoList { branches: None, .. })
+        ));
+
+        /* A client that has branches has to send them. */
+        let mut bytes = vec![0, 2];
+        bytes.extend_from_slice(&OpenttdVersion::BRANCHES.0.to_le_bytes());
+        assert!(read_packet_with_options(&bytes, options).is_err());
+    }
+}
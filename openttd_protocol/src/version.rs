@@ -0,0 +1,280 @@
+use std::cmp::Ordering;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/* The version of a branch, like "13.1", "0.50.1" or "14.0-beta1". Missing
+ * parts count as zero, and a pre-release sorts before its release. Like
+ * SemVer, pre-releases are compared identifier by identifier, with numbers
+ * compared as numbers, so "beta2" sorts before "beta10". */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BranchVersion {
+    parts: Vec<u32>,
+    pre_release: Option<String>,
+}
+
+impl BranchVersion {
+    pub fn new(parts: Vec<u32>) -> Self {
+        BranchVersion {
+            parts,
+            pre_release: None,
+        }
+    }
+
+    pub fn parts(&self) -> &[u32] {
+        &self.parts
+    }
+
+    pub fn pre_release(&self) -> Option<&str> {
+        self.pre_release.as_deref()
+    }
+}
+
+impl FromStr for BranchVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (numeric, pre_release) = match s.split_once('-') {
+            Some((numeric, pre_release)) if !pre_release.is_empty() => {
+                (numeric, Some(pre_release.to_string()))
+            }
+            Some(_) => return Err(format!("version '{}' has an empty pre-release", s)),
+            None => (s, None),
+        };
+
+        let parts = numeric
+            .split('.')
+            .map(|part| part.parse().ok())
+            .collect::<Option<Vec<u32>>>()
+            .ok_or_else(|| format!("version '{}' is not a dotted number", s))?;
+
+        Ok(BranchVersion { parts, pre_release })
+    }
+}
+
+impl Display for BranchVersion {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let parts: Vec<String> = self.parts.iter().map(|part| part.to_string()).collect();
+        formatter.write_str(&parts.join("."))?;
+        if let Some(pre_release) = &self.pre_release {
+            write!(formatter, "-{}", pre_release)?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for BranchVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        for i in 0..std::cmp::max(self.parts.len(), other.parts.len()) {
+            let ordering = self
+                .parts
+                .get(i)
+                .unwrap_or(&0)
+                .cmp(other.parts.get(i).unwrap_or(&0));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+
+        match (&self.pre_release, &other.pre_release) {
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(_), None) => Ordering::Less,
+            (Some(a), Some(b)) => compare_pre_release(a, b),
+        }
+    }
+}
+
+/* A piece of a pre-release: a run of digits or a run of anything else. */
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Identifier {
+    /* Numbers sort before text, as in SemVer. */
+    Numeric(u64),
+    Text(String),
+}
+
+/* Split "rc1.2" in "rc", 1 and 2. Dots and dashes only separate. */
+fn identifiers(pre_release: &str) -> Vec<Identifier> {
+    let mut identifiers = Vec::new();
+    let mut rest = pre_release;
+
+    while let Some(c) = rest.chars().next() {
+        if c == '.' || c == '-' {
+            rest = &rest[1..];
+            continue;
+        }
+
+        let is_digit = c.is_ascii_digit();
+        let end = rest
+            .find(|c: char| c == '.' || c == '-' || c.is_ascii_digit() != is_digit)
+            .unwrap_or(rest.len());
+        let (run, remainder) = rest.split_at(end);
+        identifiers.push(match run.parse() {
+            Ok(number) if is_digit => Identifier::Numeric(number),
+            _ => Identifier::Text(run.to_lowercase()),
+        });
+        rest = remainder;
+    }
+
+    identifiers
+}
+
+/* A pre-release with fewer identifiers sorts first when the rest is equal. */
+fn compare_pre_release(a: &str, b: &str) -> Ordering {
+    identifiers(a).cmp(&identifiers(b))
+}
+
+impl PartialOrd for BranchVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for BranchVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for BranchVersion {}
+
+/* The version as OpenTTD sends it in ClientInfoList; this is its NewGRF
+ * version. Since 12.0 this is:
+ *   (major + 16) << 24 | minor << 20 | release << 19 | revision
+ * and before that it was:
+ *   major << 28 | minor << 24 | build << 20 | release << 19 | revision
+ * Clients that send a list of branches instead set it to 0xffffffff. */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct OpenttdVersion(pub u32);
+
+impl OpenttdVersion {
+    pub const BRANCHES: OpenttdVersion = OpenttdVersion(0xffffffff);
+
+    /* Whether the client sends a list of branches instead of a version. */
+    pub fn has_branches(&self) -> bool {
+        *self == OpenttdVersion::BRANCHES
+    }
+
+    fn is_modern(&self) -> bool {
+        (self.0 >> 24) >= 12 + 16
+    }
+
+    pub fn major(&self) -> u32 {
+        if self.is_modern() {
+            (self.0 >> 24) - 16
+        } else {
+            self.0 >> 28
+        }
+    }
+
+    pub fn minor(&self) -> u32 {
+        if self.is_modern() {
+            (self.0 >> 20) & 0xf
+        } else {
+            (self.0 >> 24) & 0xf
+        }
+    }
+
+    /* Only versions before 12.0 have a build number. */
+    pub fn build(&self) -> Option<u32> {
+        if self.is_modern() {
+            None
+        } else {
+            Some((self.0 >> 20) & 0xf)
+        }
+    }
+
+    /* Whether this is a release, instead of a nightly or beta leading to it. */
+    pub fn is_release(&self) -> bool {
+        (self.0 >> 19) & 1 == 1
+    }
+
+    pub fn revision(&self) -> u32 {
+        self.0 & 0x7ffff
+    }
+
+    /* This version as it would appear in the list of branches. */
+    pub fn branch_version(&self) -> Option<BranchVersion> {
+        if self.has_branches() {
+            return None;
+        }
+
+        let mut parts = vec![self.major(), self.minor()];
+        parts.extend(self.build());
+        Some(BranchVersion::new(parts))
+    }
+}
+
+impl Display for OpenttdVersion {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self.branch_version() {
+            Some(version) => write!(formatter, "{}", version),
+            None => formatter.write_str("(branches)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(s: &str) -> BranchVersion {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn pre_release_numbers_compare_as_numbers() {
+        assert!(version("14.0-beta2") < version("14.0-beta10"));
+        assert!(version("14.0-beta10") < version("14.0-RC1"));
+        assert!(version("14.0-rc1") < version("14.0-rc1.1"));
+        assert!(version("14.0-rc1.1") < version("14.0-rc1.2"));
+        assert!(version("14.0-rc9") < version("14.0"));
+        assert!(version("14.0-1") < version("14.0-beta1"));
+        assert_eq!(version("14.0-Beta1"), version("14.0-beta1"));
+    }
+
+    #[test]
+    fn missing_parts_count_as_zero() {
+        assert_eq!(version("13"), version("13.0.0"));
+        assert!(version("13.0.1") > version("13"));
+    }
+
+    #[test]
+    fn modern_openttd_version() {
+        /* 14.1, release, revision 5. */
+        let version = OpenttdVersion((14 + 16) << 24 | 1 << 20 | 1 << 19 | 5);
+        assert_eq!(version.major(), 14);
+        assert_eq!(version.minor(), 1);
+        assert_eq!(version.build(), None);
+        assert!(version.is_release());
+        assert_eq!(version.revision(), 5);
+        assert!(!version.has_branches());
+        assert_eq!(
+            version.branch_version(),
+            Some(BranchVersion::new(vec![14, 1]))
+        );
+    }
+
+    #[test]
+    fn old_openttd_version() {
+        /* 1.10.3, nightly, revision 28004. */
+        let version = OpenttdVersion(1 << 28 | 10 << 24 | 3 << 20 | 28004);
+        assert_eq!(version.major(), 1);
+        assert_eq!(version.minor(), 10);
+        assert_eq!(version.build(), Some(3));
+        assert!(!version.is_release());
+        assert_eq!(version.revision(), 28004);
+        assert_eq!(version.to_string(), "1.10.3");
+    }
+
+    #[test]
+    fn branches_sentinel() {
+        let version = OpenttdVersion(0xffffffff);
+        assert!(version.has_branches());
+        assert_eq!(version, OpenttdVersion::BRANCHES);
+        assert_eq!(version.branch_version(), None);
+        assert_eq!(version.to_string(), "(branches)");
+    }
+}
@@ -0,0 +1,612 @@
+use std::borrow::Cow;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use serde::de::{self, DeserializeSeed, EnumAccess, SeqAccess, VariantAccess, Visitor};
+use serde::Deserialize;
+
+use super::error::{Error, Result};
+use super::Packet;
+
+/* How to decode the strings in a packet. */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StringPolicy {
+    /* Reject strings that are not valid UTF-8. */
+    #[default]
+    Strict,
+    /* Replace invalid UTF-8 with U+FFFD. */
+    Lossy,
+    /* Like Lossy, but also remove control characters (including the ones
+     * OpenTTD uses internally, in the private-use area) and cut the string
+     * to at most max_length bytes. */
+    Sanitize {
+        max_length: usize,
+    },
+}
+
+/* One step in the path to the value being decoded. */
+enum Segment {
+    Name(&'static str),
+    Index(usize),
+}
+
+/* How to decode a packet. */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecodeOptions {
+    pub strings: StringPolicy,
+    /* Ignore bytes after the last field instead of failing with
+     * PacketTooLong. Newer OpenTTD versions can add fields to a packet. */
+    pub ignore_trailing: bool,
+}
+
+struct Deserializer<'de> {
+    input: &'de [u8],
+    policy: StringPolicy,
+    /* Only used to give context to errors. */
+    length: usize,
+    value_offset: usize,
+    packet_type: Option<u8>,
+    packet_name: fn(u8) -> Option<&'static str>,
+    path: Vec<Segment>,
+}
+
+impl<'de> Deserializer<'de> {
+    fn from_bytes(
+        input: &'de [u8],
+        policy: StringPolicy,
+        packet_name: fn(u8) -> Option<&'static str>,
+    ) -> Self {
+        Deserializer {
+            input,
+            policy,
+            length: input.len(),
+            value_offset: 0,
+            packet_type: None,
+            packet_name,
+            path: Vec::new(),
+        }
+    }
+
+    fn offset(&self) -> usize {
+        self.length - self.input.len()
+    }
+
+    /* Add where decoding was when the error happened. As the path is not
+     * unwound on errors, it still points to the value that failed. */
+    fn error(&self, error: Error) -> Error {
+        let mut path = String::new();
+        for segment in &self.path {
+            match segment {
+                Segment::Name(name) if path.is_empty() => path.push_str(name),
+                Segment::Name(name) => {
+                    path.push('.');
+                    path.push_str(name);
+                }
+                Segment::Index(index) => path.push_str(&format!("[{}]", index)),
+            }
+        }
+
+        Error::Decode {
+            packet_type: self.packet_type,
+            offset: self.value_offset,
+            path,
+            error: Box::new(error),
+        }
+    }
+
+    /* OpenTTD's strings are nul-terminated. As long as the policy doesn't
+     * change the string, it borrows from the input and no copy is made. */
+    fn read_str(&mut self) -> Result<Cow<'de, str>> {
+        /* If there is no terminator, the packet is broken. */
+        let len = self
+            .input
+            .iter()
+            .position(|&c| c == 0)
+            .ok_or(Error::PacketTooShort)?;
+        let raw = &self.input[..len];
+
+        /* Remove string from buffer. */
+        self.input = &self.input[len + 1..];
+
+        match self.policy {
+            StringPolicy::Strict => std::str::from_utf8(raw)
+                .map(Cow::Borrowed)
+                .map_err(|_e| Error::InvalidString),
+            StringPolicy::Lossy => Ok(String::from_utf8_lossy(raw)),
+            StringPolicy::Sanitize { max_length } => {
+                Ok(sanitize(String::from_utf8_lossy(raw), max_length))
+            }
+        }
+    }
+}
+
+fn is_control(c: char) -> bool {
+    c.is_control() || ('\u{e000}'..='\u{f8ff}').contains(&c)
+}
+
+fn sanitize(s: Cow<str>, max_length: usize) -> Cow<str> {
+    let s = if s.chars().any(is_control) {
+        Cow::Owned(s.chars().filter(|&c| !is_control(c)).collect())
+    } else {
+        s
+    };
+    if s.len() <= max_length {
+        return s;
+    }
+
+    /* Don't cut through a UTF-8 sequence. */
+    let mut end = max_length;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    match s {
+        Cow::Borrowed(s) => Cow::Borrowed(&s[..end]),
+        Cow::Owned(mut s) => {
+            s.truncate(end);
+            Cow::Owned(s)
+        }
+    }
+}
+
+pub fn from_bytes<'a, T>(s: &'a [u8]) -> Result<T>
+where
+    T: Deserialize<'a> + Packet,
+{
+    from_bytes_with_options(s, DecodeOptions::default())
+}
+
+pub fn from_bytes_with_options<'a, T>(s: &'a [u8], options: DecodeOptions) -> Result<T>
+where
+    T: Deserialize<'a> + Packet,
+{
+    let mut deserializer = Deserializer::from_bytes(s, options.strings, T::name);
+    let t = T::deserialize(&mut deserializer).map_err(|e| deserializer.error(e))?;
+    if deserializer.input.is_empty() || options.ignore_trailing {
+        Ok(t)
+    } else {
+        deserializer.value_offset = deserializer.offset();
+        Err(deserializer.error(Error::PacketTooLong))
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        Err(Error::NotSupported("any".to_string()))
+    }
+
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.input
+            .read_u8()
+            .map_err(|_e| Error::PacketTooShort)
+            .and_then(|x| match x {
+                0 => visitor.visit_bool(false),
+                1 => visitor.visit_bool(true),
+                _ => Err(Error::ValueOutOfRange),
+            })
+    }
+
+    fn deserialize_i8<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        Err(Error::NotSupported("i8".to_string()))
+    }
+
+    fn deserialize_i16<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        Err(Error::NotSupported("i16".to_string()))
+    }
+
+    fn deserialize_i32<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        Err(Error::NotSupported("i32".to_string()))
+    }
+
+    fn deserialize_i64<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        Err(Error::NotSupported("i64".to_string()))
+    }
+
+    fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let value = self.input.read_u8().map_err(|_e| Error::PacketTooShort)?;
+        visitor.visit_u8(value)
+    }
+
+    fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let value = self
+            .input
+            .read_u16::<LittleEndian>()
+            .map_err(|_e| Error::PacketTooShort)?;
+        visitor.visit_u16(value)
+    }
+
+    fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let value = self
+            .input
+            .read_u32::<LittleEndian>()
+            .map_err(|_e| Error::PacketTooShort)?;
+        visitor.visit_u32(value)
+    }
+
+    fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let value = self
+            .input
+            .read_u64::<LittleEndian>()
+            .map_err(|_e| Error::PacketTooShort)?;
+        visitor.visit_u64(value)
+    }
+
+    fn deserialize_f32<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        Err(Error::NotSupported("f32".to_string()))
+    }
+
+    fn deserialize_f64<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        Err(Error::NotSupported("f64".to_string()))
+    }
+
+    fn deserialize_char<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        Err(Error::NotSupported("char".to_string()))
+    }
+
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        /* A string the policy changed can't be borrowed; only String and Cow
+         * fields accept those. */
+        match self.read_str()? {
+            Cow::Borrowed(s) => visitor.visit_borrowed_str(s),
+            Cow::Owned(s) => visitor.visit_string(s),
+        }
+    }
+
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        /* Bytes have no length on the wire; they are the rest of the packet. */
+        let bytes = self.input;
+        self.input = &[];
+        visitor.visit_borrowed_bytes(bytes)
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        /* Option elements can only be at the end of a struct. */
+        if self.input.is_empty() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V>(self, _name: &'static str, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        Err(Error::NotSupported("unit_struct".to_string()))
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        /* The wire has no way to tell how long a list is; #[len_prefix] says. */
+        Err(Error::InvalidSeq)
+    }
+
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_seq(ProtocolSeqAccess::new(self, len, SeqKind::Tuple))
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        _visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        Err(Error::NotSupported("tuple_struct".to_string()))
+    }
+
+    fn deserialize_map<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        Err(Error::NotSupported("map".to_string()))
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        if self.path.is_empty() {
+            self.path.push(Segment::Name(name));
+        }
+        visitor.visit_seq(ProtocolSeqAccess::new(
+            self,
+            fields.len(),
+            SeqKind::Fields(fields),
+        ))
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_enum(PacketEnum { de: self })
+    }
+
+    fn deserialize_identifier<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        Err(Error::NotSupported("identifier".to_string()))
+    }
+
+    fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        Err(Error::NotSupported("ignored_any".to_string()))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/* What the elements of a sequence are, to name them in the path. */
+enum SeqKind {
+    Fields(&'static [&'static str]),
+    Tuple,
+}
+
+struct ProtocolSeqAccess<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>,
+    len: usize,
+    kind: SeqKind,
+    index: usize,
+}
+
+impl<'a, 'de> ProtocolSeqAccess<'a, 'de> {
+    fn new(de: &'a mut Deserializer<'de>, len: usize, kind: SeqKind) -> Self {
+        ProtocolSeqAccess {
+            de,
+            len,
+            kind,
+            index: 0,
+        }
+    }
+
+    fn segment(&self) -> Option<Segment> {
+        match self.kind {
+            SeqKind::Fields(fields) => fields.get(self.index).map(|field| Segment::Name(field)),
+            SeqKind::Tuple => Some(Segment::Index(self.index)),
+        }
+    }
+}
+
+impl<'de, 'a> SeqAccess<'de> for ProtocolSeqAccess<'a, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.len == 0 {
+            return Ok(None);
+        }
+
+        let segment = self.segment();
+        self.len -= 1;
+        self.index += 1;
+
+        self.de.value_offset = self.de.offset();
+        let pushed = segment.is_some();
+        self.de.path.extend(segment);
+
+        let value = seed.deserialize(&mut *self.de)?;
+        if pushed {
+            self.de.path.pop();
+        }
+        Ok(Some(value))
+    }
+}
+
+struct PacketEnum<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>,
+}
+
+impl<'de, 'a> EnumAccess<'de> for PacketEnum<'a, 'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant)>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        /* OpenTTD's packets start with a single u8, indicating the type. The
+         * enum (see #[derive(OpenttdPacket)]) selects the variant with it. */
+        self.de.value_offset = self.de.offset();
+        let packet_type = self.de.input.first().copied();
+        let val = seed.deserialize(&mut *self.de)?;
+
+        self.de.packet_type = packet_type;
+        if let Some(name) = packet_type.and_then(self.de.packet_name) {
+            self.de.path.push(Segment::Name(name));
+        }
+        Ok((val, self))
+    }
+}
+
+impl<'de, 'a> VariantAccess<'de> for PacketEnum<'a, 'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        seed.deserialize(self.de)
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        de::Deserializer::deserialize_tuple(self.de, len, visitor)
+    }
+
+    fn struct_variant<V>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_seq(ProtocolSeqAccess::new(
+            self.de,
+            fields.len(),
+            SeqKind::Fields(fields),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_str(input: &[u8], policy: StringPolicy) -> Result<Cow<'_, str>> {
+        let mut deserializer = Deserializer::from_bytes(input, policy, |_| None);
+        let s = deserializer.read_str()?;
+        assert!(deserializer.input.is_empty());
+        Ok(s)
+    }
+
+    const SANITIZE: StringPolicy = StringPolicy::Sanitize { max_length: 4 };
+
+    #[test]
+    fn empty_string() {
+        for policy in [StringPolicy::Strict, StringPolicy::Lossy, SANITIZE] {
+            assert!(matches!(read_str(&[0], policy), Ok(Cow::Borrowed(""))));
+        }
+    }
+
+    #[test]
+    fn missing_terminator() {
+        assert!(matches!(
+            read_str(b"abc", StringPolicy::Strict),
+            Err(Error::PacketTooShort)
+        ));
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let input = b"a\xffb\0";
+        assert!(matches!(
+            read_str(input, StringPolicy::Strict),
+            Err(Error::InvalidString)
+        ));
+        assert_eq!(read_str(input, StringPolicy::Lossy).unwrap(), "a\u{fffd}b");
+    }
+
+    #[test]
+    fn sanitize_removes_control_characters() {
+        /* OpenTTD's string codes live in the private-use area. */
+        let input = "a\u{e000}\tb\u{f8ff}\0".as_bytes();
+        assert_eq!(read_str(input, StringPolicy::Lossy).unwrap().len(), 9);
+        assert_eq!(read_str(input, SANITIZE).unwrap(), "ab");
+    }
+
+    #[test]
+    fn sanitize_cuts_at_character_boundary() {
+        assert!(matches!(
+            read_str(b"abcd\0", SANITIZE),
+            Ok(Cow::Borrowed("abcd"))
+        ));
+        assert_eq!(read_str(b"abcde\0", SANITIZE).unwrap(), "abcd");
+
+        /* "€" is 3 bytes; the limit falls inside the second one. */
+        assert_eq!(read_str("a€€\0".as_bytes(), SANITIZE).unwrap(), "a€");
+    }
+}
@@ -1,4 +1,3 @@
-use std;
 use std::fmt::{self, Display};
 
 use serde::{de, ser};
@@ -15,7 +14,16 @@ pub enum Error {
     InvalidString,
     InvalidSeq,
     WriteFailure,
+    #[allow(clippy::enum_variant_names)]
     ValidationError(String),
+    /* Where in the packet decoding failed; path is like
+     * "ClientInfoExtId.content_infos[3].content_type". */
+    Decode {
+        packet_type: Option<u8>,
+        offset: usize,
+        path: String,
+        error: Box<Error>,
+    },
 }
 
 impl ser::Error for Error {
@@ -41,9 +49,24 @@ impl Display for Error {
                 formatter.write_str(format!("datatype {} not supported", datatype).as_str())
             }
             Error::InvalidString => formatter.write_str("invalid string (not valid UTF-8)"),
-            Error::InvalidSeq => formatter.write_str("invalid sequence (no VecLen used)"),
+            Error::InvalidSeq => formatter.write_str("invalid sequence (no len_prefix used)"),
             Error::WriteFailure => formatter.write_str("failed to write to buffer"),
             Error::ValidationError(msg) => formatter.write_str(msg),
+            Error::Decode {
+                packet_type,
+                offset,
+                path,
+                error,
+            } => {
+                if let Some(packet_type) = packet_type {
+                    write!(formatter, "packet type {}, ", packet_type)?;
+                }
+                write!(formatter, "offset {}", offset)?;
+                if !path.is_empty() {
+                    write!(formatter, ", {}", path)?;
+                }
+                write!(formatter, ": {}", error)
+            }
         }
     }
 }
@@ -0,0 +1,160 @@
+use std::borrow::Cow;
+use std::marker::PhantomData;
+
+use serde::de::{Deserialize, DeserializeSeed, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeTuple, Serializer};
+
+mod de;
+mod error;
+mod ser;
+
+pub use de::{from_bytes, from_bytes_with_options, DecodeOptions, StringPolicy};
+pub use error::Error;
+pub use ser::{append_to, to_bytes, to_writer};
+
+/* OpenTTD doesn't accept packets bigger than this, header included. */
+pub const TCP_MTU: usize = 32767;
+
+/* Implemented by #[derive(OpenttdPacket)] for structs with #[packet(id = N)]
+ * and for enums, where every variant is a packet. */
+pub trait Packet {
+    fn packet_type(&self) -> u8;
+
+    /* The name of the packet with this type, if it is one of these. Only
+     * used to tell which packet failed to decode. */
+    fn name(packet_type: u8) -> Option<&'static str>
+    where
+        Self: Sized;
+}
+
+/* A string that borrows from the input when it can, and owns it when the
+ * StringPolicy had to change it. Used by #[derive(OpenttdPacket)] for Cow
+ * fields; serde's own Cow always copies. */
+pub struct CowStr<'a>(Cow<'a, str>);
+
+impl<'a> CowStr<'a> {
+    pub fn into_inner(self) -> Cow<'a, str> {
+        self.0
+    }
+}
+
+impl<'de> Deserialize<'de> for CowStr<'de> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct CowStrVisitor;
+
+        impl<'de> Visitor<'de> for CowStrVisitor {
+            type Value = CowStr<'de>;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a string")
+            }
+
+            fn visit_borrowed_str<E>(self, value: &'de str) -> Result<Self::Value, E> {
+                Ok(CowStr(Cow::Borrowed(value)))
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E> {
+                Ok(CowStr(Cow::Owned(value.to_string())))
+            }
+
+            fn visit_string<E>(self, value: String) -> Result<Self::Value, E> {
+                Ok(CowStr(Cow::Owned(value)))
+            }
+        }
+
+        deserializer.deserialize_str(CowStrVisitor)
+    }
+}
+
+/* The items of a field with #[len_prefix(L)]. On the wire that is the length
+ * (as L) followed by that many items; #[derive(OpenttdPacket)] reads the
+ * length as an element of its own, and then the items with this, as a tuple
+ * of that length. */
+pub struct LenPrefixed<T>(usize, PhantomData<T>);
+
+impl<T> LenPrefixed<T> {
+    pub fn new<L, E>(len: L) -> Result<Self, E>
+    where
+        L: TryInto<usize>,
+        E: serde::de::Error,
+    {
+        let len = len
+            .try_into()
+            .map_err(|_| E::custom("length doesn't fit in usize"))?;
+        Ok(LenPrefixed(len, PhantomData))
+    }
+}
+
+impl<'de, T> DeserializeSeed<'de> for LenPrefixed<T>
+where
+    T: Deserialize<'de>,
+{
+    type Value = Vec<T>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ItemsVisitor<T>(usize, PhantomData<T>);
+
+        impl<'de, T> Visitor<'de> for ItemsVisitor<T>
+        where
+            T: Deserialize<'de>,
+        {
+            type Value = Vec<T>;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(formatter, "{} items", self.0)
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let mut items = Vec::new();
+                for i in 0..self.0 {
+                    items.push(
+                        seq.next_element()?
+                            .ok_or_else(|| serde::de::Error::invalid_length(i, &self))?,
+                    );
+                }
+                Ok(items)
+            }
+        }
+
+        deserializer.deserialize_tuple(self.0, ItemsVisitor(self.0, PhantomData))
+    }
+}
+
+/* Sends a field with #[len_prefix(L)]: its length as L, then its items. */
+pub struct LenPrefixedRef<'a, L, T>(PhantomData<L>, &'a [T]);
+
+impl<'a, L, T> LenPrefixedRef<'a, L, T> {
+    pub fn new(items: &'a [T]) -> Self {
+        LenPrefixedRef(PhantomData, items)
+    }
+}
+
+impl<L, T> Serialize for LenPrefixedRef<'_, L, T>
+where
+    L: Serialize + TryFrom<usize>,
+    T: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let len = L::try_from(self.1.len())
+            .map_err(|_| serde::ser::Error::custom("too many items for length prefix"))?;
+
+        let mut state = serializer.serialize_tuple(self.1.len() + 1)?;
+        state.serialize_element(&len)?;
+        for item in self.1 {
+            state.serialize_element(item)?;
+        }
+        state.end()
+    }
+}
@@ -2,33 +2,70 @@ use byteorder::{LittleEndian, WriteBytesExt};
 use serde::{ser, Serialize};
 
 use super::error::{Error, Result};
-use super::ServerPacket;
+use super::{Packet, TCP_MTU};
 
-struct Serializer {
-    output: Vec<u8>,
+struct Serializer<'a> {
+    output: &'a mut Vec<u8>,
 }
 
 pub fn to_bytes<T>(value: &T) -> Result<Vec<u8>>
 where
-    T: Serialize + ServerPacket,
+    T: Serialize + Packet,
 {
-    let mut serializer = Serializer { output: Vec::new() };
+    let mut output = Vec::new();
+    append_to(&mut output, value)?;
+    Ok(output)
+}
+
+/* Packets need their length up front, so a packet is always built in memory
+ * before it is written. */
+pub fn to_writer<W, T>(writer: &mut W, value: &T) -> Result<()>
+where
+    W: std::io::Write,
+    T: Serialize + Packet,
+{
+    let output = to_bytes(value)?;
+    writer.write_all(&output).map_err(|_e| Error::WriteFailure)
+}
+
+/* Add the packet to the end of output, which can already hold other packets.
+ * On failure, output is left as it was. */
+pub fn append_to<T>(output: &mut Vec<u8>, value: &T) -> Result<()>
+where
+    T: Serialize + Packet,
+{
+    let start = output.len();
+    let result = serialize_packet(output, start, value);
+    if result.is_err() {
+        output.truncate(start);
+    }
+    result
+}
+
+fn serialize_packet<T>(output: &mut Vec<u8>, start: usize, value: &T) -> Result<()>
+where
+    T: Serialize + Packet,
+{
+    let mut serializer = Serializer { output };
 
     /* Reserve room for the length and add the type. */
     serializer.output.write_u16::<LittleEndian>(0).unwrap();
-    serializer.output.write_u8(T::TYPE).unwrap();
+    serializer.output.write_u8(value.packet_type()).unwrap();
 
     value.serialize(&mut serializer)?;
 
     /* Add the actual length to the beginning. */
-    let len = serializer.output.len();
-    serializer.output[0] = len as u8;
-    serializer.output[1] = (len >> 8) as u8;
+    let len = serializer.output.len() - start;
+    if len > TCP_MTU {
+        return Err(Error::PacketTooLong);
+    }
+    serializer.output[start] = len as u8;
+    serializer.output[start + 1] = (len >> 8) as u8;
 
-    Ok(serializer.output)
+    Ok(())
 }
 
-impl<'a> ser::Serializer for &'a mut Serializer {
+impl ser::Serializer for &mut Serializer<'_> {
     type Ok = ();
     type Error = Error;
 
@@ -103,19 +140,23 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         Ok(())
     }
 
-    fn serialize_bytes(self, _v: &[u8]) -> Result<()> {
-        Err(Error::NotSupported("bytes".to_string()))
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        /* Bytes have no length on the wire; they are the rest of the packet. */
+        self.output.extend_from_slice(v);
+        Ok(())
     }
 
+    /* Option elements can only be at the end of a struct; None is simply
+     * the packet ending early. */
     fn serialize_none(self) -> Result<()> {
-        Err(Error::NotSupported("none".to_string()))
+        Ok(())
     }
 
-    fn serialize_some<T>(self, _value: &T) -> Result<()>
+    fn serialize_some<T>(self, value: &T) -> Result<()>
     where
         T: ?Sized + Serialize,
     {
-        Err(Error::NotSupported("some".to_string()))
+        value.serialize(self)
     }
 
     fn serialize_unit(self) -> Result<()> {
@@ -147,12 +188,13 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         _name: &'static str,
         _variant_index: u32,
         _variant: &'static str,
-        _value: &T,
+        value: &T,
     ) -> Result<()>
     where
         T: ?Sized + Serialize,
     {
-        Err(Error::NotSupported("newtype_variant".to_string()))
+        /* The variant is the packet type, which to_bytes already wrote. */
+        value.serialize(self)
     }
 
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
@@ -196,11 +238,12 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         _variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStructVariant> {
-        Err(Error::NotSupported("struct_variant".to_string()))
+        /* The variant is the packet type, which to_bytes already wrote. */
+        Ok(self)
     }
 }
 
-impl<'a> ser::SerializeSeq for &'a mut Serializer {
+impl ser::SerializeSeq for &mut Serializer<'_> {
     type Ok = ();
     type Error = Error;
 
@@ -217,7 +260,7 @@ impl<'a> ser::SerializeSeq for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeTuple for &'a mut Serializer {
+impl ser::SerializeTuple for &mut Serializer<'_> {
     type Ok = ();
     type Error = Error;
 
@@ -233,7 +276,7 @@ impl<'a> ser::SerializeTuple for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeTupleStruct for &'a mut Serializer {
+impl ser::SerializeTupleStruct for &mut Serializer<'_> {
     type Ok = ();
     type Error = Error;
 
@@ -249,7 +292,7 @@ impl<'a> ser::SerializeTupleStruct for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeTupleVariant for &'a mut Serializer {
+impl ser::SerializeTupleVariant for &mut Serializer<'_> {
     type Ok = ();
     type Error = Error;
 
@@ -265,7 +308,7 @@ impl<'a> ser::SerializeTupleVariant for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeMap for &'a mut Serializer {
+impl ser::SerializeMap for &mut Serializer<'_> {
     type Ok = ();
     type Error = Error;
 
@@ -288,7 +331,7 @@ impl<'a> ser::SerializeMap for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeStruct for &'a mut Serializer {
+impl ser::SerializeStruct for &mut Serializer<'_> {
     type Ok = ();
     type Error = Error;
 
@@ -304,7 +347,7 @@ impl<'a> ser::SerializeStruct for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeStructVariant for &'a mut Serializer {
+impl ser::SerializeStructVariant for &mut Serializer<'_> {
     type Ok = ();
     type Error = Error;
 
@@ -0,0 +1,185 @@
+use clap::Parser;
+use serde::Deserialize;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    /* Only things that went wrong. */
+    Error,
+    /* Also what the workers log. */
+    Info,
+    /* Also every connection. */
+    Debug,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /* Connections beyond this wait until another one closes. */
+    pub max_connections: Option<usize>,
+    /* Bytes of linear memory a single guest instance may use. */
+    pub max_memory: Option<usize>,
+    /* Fuel a single connection may use; roughly one unit per WASM
+     * instruction. Time spent waiting on the client or on the host is free. */
+    pub max_fuel: Option<u64>,
+    /* Seconds a read may wait for the client to send anything. */
+    pub idle_timeout: Option<u64>,
+    /* Seconds after which a connection gets no more reads. */
+    pub session_timeout: Option<u64>,
+    /* Bytes per second a client has to keep up once it started sending a
+     * packet, after a grace period of one second. */
+    pub min_read_rate: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Log {
+    pub level: LogLevel,
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Log {
+            level: LogLevel::Info,
+        }
+    }
+}
+
+/* Relative paths are relative to the working directory, not to the config
+ * file. */
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: Vec<SocketAddr>,
+    pub module: PathBuf,
+    pub index: PathBuf,
+    pub storage: PathBuf,
+    pub limits: Limits,
+    pub log: Log,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: vec![SocketAddr::from(([127, 0, 0, 1], 12345))],
+            module: PathBuf::from(
+                "../bananas_server/target/wasm32-unknown-unknown/release/bananas_server.wasm",
+            ),
+            index: PathBuf::from("../index"),
+            storage: PathBuf::from("../storage"),
+            limits: Limits::default(),
+            log: Log::default(),
+        }
+    }
+}
+
+/* Everything given on the command line overrides the config file. */
+#[derive(Parser, Debug)]
+#[command(about = "Serves the OpenTTD content protocol with a WASM module per connection")]
+struct Args {
+    /// TOML file to read the configuration from.
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Address to listen on, like "0.0.0.0:3978" or "[::]:3978"; can be given more than once.
+    #[arg(short, long)]
+    listen: Vec<SocketAddr>,
+
+    /// WASM module to run for every connection.
+    #[arg(long)]
+    module: Option<PathBuf>,
+
+    /// Folder with the BaNaNaS index.
+    #[arg(long)]
+    index: Option<PathBuf>,
+
+    /// Folder with the content files.
+    #[arg(long)]
+    storage: Option<PathBuf>,
+
+    /// Maximum amount of connections handled at the same time.
+    #[arg(long)]
+    max_connections: Option<usize>,
+
+    /// Maximum amount of memory, in bytes, of a single connection.
+    #[arg(long)]
+    max_memory: Option<usize>,
+
+    /// Maximum amount of fuel (about one per WASM instruction) a single connection may use.
+    #[arg(long)]
+    max_fuel: Option<u64>,
+
+    /// Seconds a connection may stay silent.
+    #[arg(long)]
+    idle_timeout: Option<u64>,
+
+    /// Seconds a connection may last.
+    #[arg(long)]
+    session_timeout: Option<u64>,
+
+    /// Bytes per second a client has to send while in the middle of a packet.
+    #[arg(long)]
+    min_read_rate: Option<u64>,
+
+    /// How much to log.
+    #[arg(long, value_enum)]
+    log_level: Option<LogLevel>,
+}
+
+pub fn load() -> Result<Config, Box<dyn Error>> {
+    let args = Args::parse();
+
+    let mut config = match &args.config {
+        Some(path) => {
+            let data =
+                std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            toml::from_str(&data).map_err(|e| format!("{}: {}", path.display(), e))?
+        }
+        None => Config::default(),
+    };
+
+    if !args.listen.is_empty() {
+        config.listen = args.listen;
+    }
+    if let Some(module) = args.module {
+        config.module = module;
+    }
+    if let Some(index) = args.index {
+        config.index = index;
+    }
+    if let Some(storage) = args.storage {
+        config.storage = storage;
+    }
+    if let Some(max_connections) = args.max_connections {
+        config.limits.max_connections = Some(max_connections);
+    }
+    if let Some(max_memory) = args.max_memory {
+        config.limits.max_memory = Some(max_memory);
+    }
+    if let Some(max_fuel) = args.max_fuel {
+        config.limits.max_fuel = Some(max_fuel);
+    }
+    if let Some(idle_timeout) = args.idle_timeout {
+        config.limits.idle_timeout = Some(idle_timeout);
+    }
+    if let Some(session_timeout) = args.session_timeout {
+        config.limits.session_timeout = Some(session_timeout);
+    }
+    if let Some(min_read_rate) = args.min_read_rate {
+        config.limits.min_read_rate = Some(min_read_rate);
+    }
+    if let Some(level) = args.log_level {
+        config.log.level = level;
+    }
+
+    if config.limits.min_read_rate == Some(0) {
+        return Err("min_read_rate has to be at least 1".into());
+    }
+    if config.listen.is_empty() {
+        return Err("no addresses to listen on".into());
+    }
+    Ok(config)
+}
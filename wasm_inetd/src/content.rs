@@ -0,0 +1,112 @@
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/* OpenTTD's packet framing, for the file data the host sends by itself. */
+const TCP_MTU: usize = 32767;
+const HEADER_SIZE: usize = 3;
+const PACKET_CONTENT_SERVER_CONTENT: u8 = 6;
+
+/* Send "length" bytes of "file" as ServerContent packets, and end with an
+ * empty one: only that tells OpenTTD the file is complete, after which it
+ * extracts it and expects the header of the next file. A file that ends
+ * before "length" is an error; the client would keep waiting for the rest. */
+pub async fn send_content<R, W>(file: &mut R, writer: &mut W, length: usize) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0; TCP_MTU];
+    let mut remaining = length;
+    while remaining > 0 {
+        let chunk = std::cmp::min(remaining, TCP_MTU - HEADER_SIZE);
+        let n = match file
+            .read(&mut buf[HEADER_SIZE..HEADER_SIZE + chunk])
+            .await?
+        {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => n,
+        };
+
+        write_header(&mut buf, n);
+        writer.write_all(&buf[..HEADER_SIZE + n]).await?;
+        remaining -= n;
+    }
+
+    write_header(&mut buf, 0);
+    writer.write_all(&buf[..HEADER_SIZE]).await
+}
+
+fn write_header(buf: &mut [u8], data_length: usize) {
+    let packet_length = (HEADER_SIZE + data_length) as u16;
+    buf[..2].copy_from_slice(&packet_length.to_le_bytes());
+    buf[2] = PACKET_CONTENT_SERVER_CONTENT;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Split a stream of packets into (type, data) pairs. */
+    fn packets(mut stream: &[u8]) -> Vec<(u8, &[u8])> {
+        let mut packets = Vec::new();
+        while !stream.is_empty() {
+            let len = u16::from_le_bytes([stream[0], stream[1]]) as usize;
+            packets.push((stream[2], &stream[HEADER_SIZE..len]));
+            stream = &stream[len..];
+        }
+        packets
+    }
+
+    fn run<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn full_download() {
+        let file: Vec<u8> = (0..TCP_MTU * 2).map(|i| i as u8).collect();
+
+        /* The guest sends the header (a ServerContent with the file's
+         * details) before handing over to the host. */
+        let header = [5, 0, PACKET_CONTENT_SERVER_CONTENT, 1, 2];
+        let mut stream = header.to_vec();
+        run(send_content(&mut &file[..], &mut stream, file.len())).unwrap();
+
+        let packets = packets(&stream);
+        let chunk = TCP_MTU - HEADER_SIZE;
+        assert_eq!(packets.len(), 5);
+        assert_eq!(packets[0], (PACKET_CONTENT_SERVER_CONTENT, &[1, 2][..]));
+        assert_eq!(packets[1], (PACKET_CONTENT_SERVER_CONTENT, &file[..chunk]));
+        assert_eq!(
+            packets[2],
+            (PACKET_CONTENT_SERVER_CONTENT, &file[chunk..chunk * 2])
+        );
+        assert_eq!(
+            packets[3],
+            (PACKET_CONTENT_SERVER_CONTENT, &file[chunk * 2..])
+        );
+        assert_eq!(packets[4], (PACKET_CONTENT_SERVER_CONTENT, &[][..]));
+    }
+
+    #[test]
+    fn empty_file() {
+        let mut stream = Vec::new();
+        run(send_content(&mut &[][..], &mut stream, 0)).unwrap();
+        assert_eq!(stream, [3, 0, PACKET_CONTENT_SERVER_CONTENT]);
+    }
+
+    #[test]
+    fn file_shorter_than_length() {
+        let mut stream = Vec::new();
+        let error = run(send_content(&mut &[1, 2, 3][..], &mut stream, 4)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        /* The client must not be told the file is complete. */
+        assert_eq!(
+            packets(&stream),
+            [(PACKET_CONTENT_SERVER_CONTENT, &[1, 2, 3][..])]
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use std::error::Error;
+use std::fmt;
+use wasmtime::ResourceLimiter;
+
+/* Fuel is handed out in slices of this size; in between slices the guest
+ * yields, so a busy guest doesn't keep a tokio worker to itself. */
+pub const FUEL_SLICE: u64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy)]
+pub enum LimitExceeded {
+    Memory(usize),
+    Cpu,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LimitExceeded::Memory(desired) => {
+                write!(f, "memory limit exceeded (wanted {} bytes)", desired)
+            }
+            LimitExceeded::Cpu => write!(f, "CPU limit exceeded"),
+        }
+    }
+}
+
+impl Error for LimitExceeded {}
+
+/* Instead of failing the allocation, which the guest can't recover from
+ * anyway, stop the instance with an error that says why. */
+pub struct MemoryLimiter {
+    pub max_memory: usize,
+}
+
+impl ResourceLimiter for MemoryLimiter {
+    fn memory_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> wasmtime::Result<bool> {
+        if desired > self.max_memory {
+            return Err(LimitExceeded::Memory(desired).into());
+        }
+        Ok(true)
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: u32,
+        _desired: u32,
+        _maximum: Option<u32>,
+    ) -> wasmtime::Result<bool> {
+        Ok(true)
+    }
+}
@@ -1,59 +1,139 @@
+use bananas_catalog::{index, Catalog, ContentType, Package};
+use config::LogLevel;
 use futures::lock::Mutex;
 use futures::Future;
+use limits::{LimitExceeded, MemoryLimiter, FUEL_SLICE};
+use memory::MemoryError;
+use std::collections::HashMap;
 use std::error::Error;
+use std::io::SeekFrom;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::{TcpListener, TcpStream};
-use wasmtime::{Caller, Config, Engine, Extern, Linker, Module, Store};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::{watch, Semaphore};
+use tokio::time::{timeout_at, Instant};
+use wasmtime::{Caller, Config, Engine, Linker, Module, Store};
+
+mod config;
+mod content;
+mod limits;
+mod memory;
+
+/* Everything that is shared between all connections. */
+struct Shared {
+    catalog: Catalog,
+    /* Listing a content type is the most common and biggest lookup; these
+     * are serialized only once, and hold only what a listing can offer. */
+    by_content_type: HashMap<ContentType, Vec<u8>>,
+    storage: PathBuf,
+    log_level: LogLevel,
+    limits: config::Limits,
+}
 
 struct ProcessEnv {
+    /* Who is on the other end; used to tell connections apart in logs. */
+    peer: SocketAddr,
     reader: Arc<Mutex<OwnedReadHalf>>,
     writer: Arc<Mutex<OwnedWriteHalf>>,
+    shared: Arc<Shared>,
+    limiter: MemoryLimiter,
+    session_deadline: Option<Instant>,
+    /* When the client started sending the packet the guest is waiting on,
+     * and how many bytes of it arrived since. */
+    packet: Option<(Instant, u64)>,
+}
+
+/* Values "read" returns besides the amount of bytes read. */
+const READ_FAILURE: i32 = -1;
+const READ_IDLE_TIMEOUT: i32 = -2;
+const READ_SESSION_EXPIRED: i32 = -3;
+const READ_TOO_SLOW: i32 = -4;
+
+impl ProcessEnv {
+    /* The first of the limits a read runs into, with the value to return
+     * when it does. */
+    fn read_deadline(&mut self, in_packet: bool) -> Option<(Instant, i32)> {
+        let now = Instant::now();
+        let limits = &self.shared.limits;
+        let mut deadlines = Vec::new();
+
+        if let Some(timeout) = limits.idle_timeout {
+            deadlines.push((now + Duration::from_secs(timeout), READ_IDLE_TIMEOUT));
+        }
+        if let Some(deadline) = self.session_deadline {
+            deadlines.push((deadline, READ_SESSION_EXPIRED));
+        }
+
+        if !in_packet {
+            self.packet = None;
+        } else if let Some(rate) = limits.min_read_rate {
+            let (start, received) = *self.packet.get_or_insert((now, 0));
+            let allowed = Duration::from_secs_f64((received + rate) as f64 / rate as f64);
+            deadlines.push((start + allowed, READ_TOO_SLOW));
+        }
+
+        deadlines.into_iter().min_by_key(|(deadline, _)| *deadline)
+    }
 }
 
 fn console_log(
     mut caller: Caller<'_, ProcessEnv>,
     ptr: i32,
     length: i32,
-) -> Box<dyn Future<Output = ()> + Send + '_> {
+) -> Box<dyn Future<Output = wasmtime::Result<()>> + Send + '_> {
     Box::new(async move {
-        let mem = match caller.get_export("memory") {
-            Some(Extern::Memory(mem)) => mem,
-            _ => panic!("failed to find WASM memory"),
-        };
-
-        let data = mem.data(&caller)[ptr as usize..ptr as usize + length as usize].as_ref();
-        let string = match std::str::from_utf8(data) {
-            Ok(string) => string,
-            Err(_) => "(invalid string)",
-        };
+        let data = memory::slice(&mut caller, ptr, length)?;
+        let string = std::str::from_utf8(data)
+            .unwrap_or("(invalid string)")
+            .to_string();
 
         /* Log it to the console. */
-        println!("worker: {}", string);
-
-        ()
+        if caller.data().shared.log_level >= LogLevel::Info {
+            println!("worker {}: {}", caller.data().peer, string);
+        }
+        Ok(())
     })
 }
 
+/* "in_packet" tells whether the guest already has part of a packet; from then
+ * on the client has to keep sending at a minimum rate. */
 fn read(
     mut caller: Caller<'_, ProcessEnv>,
     ptr: i32,
     length: i32,
-) -> Box<dyn Future<Output = i32> + Send + '_> {
+    in_packet: i32,
+) -> Box<dyn Future<Output = wasmtime::Result<i32>> + Send + '_> {
     Box::new(async move {
+        let deadline = caller.data_mut().read_deadline(in_packet != 0);
+
         let reader = caller.data().reader.clone();
         let mut reader = reader.lock().await;
 
-        let mem = caller.get_export("memory").unwrap().into_memory().unwrap();
-        let mut data =
-            mem.data_mut(&mut caller)[ptr as usize..ptr as usize + length as usize].as_mut();
+        let data = memory::slice_mut(&mut caller, ptr, length)?;
 
-        match reader.read_exact(&mut data).await {
-            Ok(n) => n as i32,
-            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => 0i32,
-            Err(_) => -1i32,
-        }
+        /* Return whatever is available; 0 means the connection is closed. */
+        let result = match deadline {
+            Some((deadline, code)) => match timeout_at(deadline, reader.read(data)).await {
+                Ok(result) => result,
+                Err(_) => return Ok(code),
+            },
+            None => reader.read(data).await,
+        };
+
+        Ok(match result {
+            Ok(n) => {
+                if let Some((_, received)) = &mut caller.data_mut().packet {
+                    *received += n as u64;
+                }
+                n as i32
+            }
+            Err(_) => READ_FAILURE,
+        })
     })
 }
 
@@ -61,79 +141,455 @@ fn write(
     mut caller: Caller<'_, ProcessEnv>,
     ptr: i32,
     length: i32,
-) -> Box<dyn Future<Output = i32> + Send + '_> {
+) -> Box<dyn Future<Output = wasmtime::Result<i32>> + Send + '_> {
     Box::new(async move {
         let writer = caller.data().writer.clone();
         let mut writer = writer.lock().await;
 
-        let mem = caller.get_export("memory").unwrap().into_memory().unwrap();
-        let data = mem.data(&caller)[ptr as usize..ptr as usize + length as usize].as_ref();
+        let data = memory::slice(&mut caller, ptr, length)?;
 
-        match writer.write(&data).await {
-            Ok(n) => n as i32,
+        Ok(match writer.write_all(data).await {
+            Ok(()) => length,
             Err(_) => -1i32,
+        })
+    })
+}
+
+/* The guest passes a content type as i32; all of it has to be valid, not
+ * just the lowest byte. */
+fn guest_content_type(content_type: i32) -> Option<ContentType> {
+    let content_type = u8::try_from(content_type).ok()?;
+    ContentType::try_from(content_type).ok()
+}
+
+fn content_path(shared: &Shared, content_id: i32) -> Option<PathBuf> {
+    shared
+        .catalog
+        .by_content_id(content_id as u32)
+        .map(|(package, version)| index::storage_path(&shared.storage, package, version))
+}
+
+/* Copy the result of a catalog lookup into guest memory. This returns the
+ * size of the result; if the guest has no room for it, nothing is copied and
+ * the guest is expected to try again with a bigger buffer. */
+fn copy_result(
+    caller: &mut Caller<'_, ProcessEnv>,
+    result: &[u8],
+    ptr: i32,
+    length: i32,
+) -> wasmtime::Result<i32> {
+    let buf = memory::slice_mut(caller, ptr, length)?;
+    if result.len() <= buf.len() {
+        buf[..result.len()].copy_from_slice(result);
+    }
+    Ok(result.len() as i32)
+}
+
+fn serialize_packages(packages: &[Package]) -> Vec<u8> {
+    bincode::serialize(packages).expect("packages are always serializable")
+}
+
+fn catalog_by_content_type(
+    mut caller: Caller<'_, ProcessEnv>,
+    content_type: i32,
+    ptr: i32,
+    length: i32,
+) -> Box<dyn Future<Output = wasmtime::Result<i32>> + Send + '_> {
+    Box::new(async move {
+        let shared = caller.data().shared.clone();
+        let content_type = match guest_content_type(content_type) {
+            Some(content_type) => content_type,
+            None => return Ok(-1i32),
+        };
+
+        copy_result(
+            &mut caller,
+            &shared.by_content_type[&content_type],
+            ptr,
+            length,
+        )
+    })
+}
+
+fn catalog_by_content_id(
+    mut caller: Caller<'_, ProcessEnv>,
+    content_id: i32,
+    ptr: i32,
+    length: i32,
+) -> Box<dyn Future<Output = wasmtime::Result<i32>> + Send + '_> {
+    Box::new(async move {
+        let shared = caller.data().shared.clone();
+        let packages: Vec<Package> = shared
+            .catalog
+            .by_content_id(content_id as u32)
+            .map(|(package, version)| package.with_versions([version]))
+            .into_iter()
+            .collect();
+
+        copy_result(&mut caller, &serialize_packages(&packages), ptr, length)
+    })
+}
+
+fn catalog_by_unique_id(
+    mut caller: Caller<'_, ProcessEnv>,
+    content_type: i32,
+    unique_id: i32,
+    ptr: i32,
+    length: i32,
+) -> Box<dyn Future<Output = wasmtime::Result<i32>> + Send + '_> {
+    Box::new(async move {
+        let shared = caller.data().shared.clone();
+        let content_type = match guest_content_type(content_type) {
+            Some(content_type) => content_type,
+            None => return Ok(-1i32),
+        };
+        let packages: Vec<Package> = shared
+            .catalog
+            .by_unique_id(content_type, unique_id as u32)
+            .cloned()
+            .into_iter()
+            .collect();
+
+        copy_result(&mut caller, &serialize_packages(&packages), ptr, length)
+    })
+}
+
+fn catalog_by_md5(
+    mut caller: Caller<'_, ProcessEnv>,
+    content_type: i32,
+    unique_id: i32,
+    md5_ptr: i32,
+    ptr: i32,
+    length: i32,
+) -> Box<dyn Future<Output = wasmtime::Result<i32>> + Send + '_> {
+    Box::new(async move {
+        let shared = caller.data().shared.clone();
+        let content_type = match guest_content_type(content_type) {
+            Some(content_type) => content_type,
+            None => return Ok(-1i32),
+        };
+
+        let mut md5 = [0; 16];
+        md5.copy_from_slice(memory::slice(&mut caller, md5_ptr, 16)?);
+
+        let packages: Vec<Package> = shared
+            .catalog
+            .by_md5(content_type, unique_id as u32, &md5)
+            .map(|(package, version)| package.with_versions([version]))
+            .into_iter()
+            .collect();
+
+        copy_result(&mut caller, &serialize_packages(&packages), ptr, length)
+    })
+}
+
+/* Send "length" bytes of a content file, starting at "offset", straight from
+ * disk to the client. The guest decides which content_ids are allowed; the
+ * host only frames the data, including the empty packet that ends the file.
+ * Returns "length", or -1 if not all of it could be sent; in that case the
+ * file is not ended, and the guest has to drop the connection. */
+fn send_file(
+    caller: Caller<'_, ProcessEnv>,
+    content_id: i32,
+    offset: i32,
+    length: i32,
+) -> Box<dyn Future<Output = i32> + Send + '_> {
+    Box::new(async move {
+        if offset < 0 || length < 0 {
+            return -1i32;
+        }
+
+        let path = match content_path(&caller.data().shared, content_id) {
+            Some(path) => path,
+            None => return -1i32,
+        };
+        let mut file = match tokio::fs::File::open(path).await {
+            Ok(file) => file,
+            Err(_) => return -1i32,
+        };
+        if file.seek(SeekFrom::Start(offset as u64)).await.is_err() {
+            return -1i32;
+        }
+
+        let writer = caller.data().writer.clone();
+        let mut writer = writer.lock().await;
+        match content::send_content(&mut file, &mut *writer, length as usize).await {
+            Ok(()) => length,
+            Err(e) => {
+                println!("Failed to send content {}: {}", content_id, e);
+                -1i32
+            }
         }
     })
 }
 
+/* wasmtime reports a stopped instance with a backtrace on top of the reason;
+ * for the reasons the host caused itself, only the reason is worth logging. */
+fn guest_error(error: wasmtime::Error) -> Box<dyn Error> {
+    if let Some(wasmtime::Trap::OutOfFuel) = error.downcast_ref::<wasmtime::Trap>() {
+        return Box::new(LimitExceeded::Cpu);
+    }
+    if let Some(limit) = error.downcast_ref::<LimitExceeded>() {
+        return Box::new(*limit);
+    }
+    if let Some(memory_error) = error.downcast_ref::<MemoryError>() {
+        return Box::new(*memory_error);
+    }
+    error.into()
+}
+
 async fn process(
     socket: TcpStream,
     engine: &Engine,
     module: &Module,
     linker: &Linker<ProcessEnv>,
+    shared: Arc<Shared>,
 ) -> Result<(), Box<dyn Error>> {
+    let peer = socket.peer_addr()?;
     let (reader, writer) = socket.into_split();
+    let max_fuel = shared.limits.max_fuel;
 
     let mut store = Store::new(
-        &engine,
+        engine,
         ProcessEnv {
+            peer,
             reader: Arc::new(Mutex::new(reader)),
             writer: Arc::new(Mutex::new(writer)),
+            limiter: MemoryLimiter {
+                max_memory: shared.limits.max_memory.unwrap_or(usize::MAX),
+            },
+            session_deadline: shared
+                .limits
+                .session_timeout
+                .map(|timeout| Instant::now() + Duration::from_secs(timeout)),
+            packet: None,
+            shared,
         },
     );
-    let instance = linker.instantiate_async(&mut store, &module).await?;
+    store.limiter(|env| &mut env.limiter);
+    if let Some(max_fuel) = max_fuel {
+        store.add_fuel(FUEL_SLICE.min(max_fuel))?;
+        store.out_of_fuel_async_yield(max_fuel.saturating_sub(FUEL_SLICE) / FUEL_SLICE, FUEL_SLICE);
+    }
+
+    let instance = linker
+        .instantiate_async(&mut store, module)
+        .await
+        .map_err(guest_error)?;
 
     let connect = instance.get_typed_func::<(), ()>(&mut store, "connect")?;
-    connect.call_async(&mut store, ()).await?;
+    connect
+        .call_async(&mut store, ())
+        .await
+        .map_err(guest_error)?;
 
     Ok(())
 }
 
-async fn listen() -> Result<(), Box<dyn Error>> {
-    let wasm_bytes = std::fs::read(
-        "../bananas_server/target/wasm32-unknown-unknown/release/bananas_server.wasm",
+/* Bind without tokio's help, so IPv6 sockets can be made IPv6-only; that way
+ * "0.0.0.0" and "[::]" on the same port can be listened on side by side. */
+fn bind(address: SocketAddr) -> Result<TcpListener, Box<dyn Error>> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(address),
+        socket2::Type::STREAM,
+        Some(socket2::Protocol::TCP),
     )?;
+    if address.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(1024)?;
 
-    let engine = Engine::new(Config::new().async_support(true))?;
-    let module = Module::new(&engine, wasm_bytes)?;
-
-    let mut linker = Linker::new(&engine);
-    linker.func_wrap2_async("env", "console_log", console_log)?;
-    linker.func_wrap2_async("env", "read", read)?;
-    linker.func_wrap2_async("env", "write", write)?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
 
-    /* Listen for incoming TCP connections. */
-    let listener = TcpListener::bind("127.0.0.1:12345").await?;
+/* Out of file descriptors, for this process (EMFILE) or the system (ENFILE).
+ * Accepting again right away would only fail again. */
+const EMFILE: i32 = 24;
+const ENFILE: i32 = 23;
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
 
+async fn accept(
+    listener: TcpListener,
+    engine: Engine,
+    module: watch::Receiver<Module>,
+    linker: Linker<ProcessEnv>,
+    shared: Arc<Shared>,
+    connections: Option<Arc<Semaphore>>,
+) -> Result<(), Box<dyn Error>> {
     loop {
-        /* Blocking wait for a new connection. */
-        let (socket, _) = listener.accept().await?;
+        /* Wait for room for another connection before accepting one. */
+        let permit = match &connections {
+            Some(connections) => Some(connections.clone().acquire_owned().await?),
+            None => None,
+        };
 
-        let module = module.clone();
+        /* Blocking wait for a new connection. A failed accept only concerns
+         * that connection; the listener keeps going. */
+        let (socket, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                println!("Failed to accept connection: {}", e);
+                if matches!(e.raw_os_error(), Some(EMFILE) | Some(ENFILE)) {
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                }
+                continue;
+            }
+        };
+        if shared.log_level >= LogLevel::Debug {
+            println!("Accepted connection from {}", peer);
+        }
+
+        /* The connection keeps this module, even if it is reloaded meanwhile. */
+        let module = module.borrow().clone();
         let linker = linker.clone();
         let engine = engine.clone();
+        let shared = shared.clone();
 
         /* Spawn a new task to process the new connection. */
         tokio::spawn(async move {
-            match process(socket, &engine, &module, &linker).await {
+            let log_level = shared.log_level;
+            match process(socket, &engine, &module, &linker, shared).await {
                 Ok(_) => {}
-                Err(e) => println!("Failed to process connection: {}", e),
+                Err(e) => println!("Failed to process connection from {}: {}", peer, e),
+            }
+            if log_level >= LogLevel::Debug {
+                println!("Closed connection from {}", peer);
             }
+            drop(permit);
         });
     }
 }
 
+fn compile(engine: &Engine, linker: &Linker<ProcessEnv>, path: &Path) -> wasmtime::Result<Module> {
+    let module = Module::new(engine, std::fs::read(path)?)?;
+
+    /* Rather find out now than on the first connection that it doesn't fit
+     * this host. */
+    linker.instantiate_pre(&module)?;
+    if module.get_export("connect").is_none() {
+        return Err(wasmtime::Error::msg("no \"connect\" export"));
+    }
+
+    Ok(module)
+}
+
+/* On SIGHUP, compile the module again and hand it to new connections. A module
+ * that fails to compile is ignored, and the old one stays in use. */
+async fn reload(
+    mut hangup: Signal,
+    path: PathBuf,
+    engine: Engine,
+    linker: Linker<ProcessEnv>,
+    module: watch::Sender<Module>,
+    log_level: LogLevel,
+) -> Result<(), Box<dyn Error>> {
+    while hangup.recv().await.is_some() {
+        let (engine, linker, file) = (engine.clone(), linker.clone(), path.clone());
+        let compiled =
+            tokio::task::spawn_blocking(move || compile(&engine, &linker, &file)).await?;
+
+        match compiled {
+            Ok(compiled) => {
+                module.send_replace(compiled);
+                if log_level >= LogLevel::Info {
+                    println!("Reloaded {}", path.display());
+                }
+            }
+            Err(e) => println!("Failed to reload {}: {}", path.display(), e),
+        }
+    }
+
+    Ok(())
+}
+
+async fn listen(config: config::Config) -> Result<(), Box<dyn Error>> {
+    let storage = config.storage;
+    let catalog = index::load(&config.index, &storage)?;
+    let by_content_type = ContentType::ALL
+        .into_iter()
+        .map(|content_type| {
+            let packages = catalog.by_content_type(content_type);
+            (content_type, serialize_packages(&packages))
+        })
+        .collect();
+    let shared = Arc::new(Shared {
+        catalog,
+        by_content_type,
+        storage,
+        log_level: config.log.level,
+        limits: config.limits,
+    });
+
+    let engine = Engine::new(
+        Config::new()
+            .async_support(true)
+            .consume_fuel(shared.limits.max_fuel.is_some()),
+    )?;
+    let mut linker = Linker::new(&engine);
+    linker.func_wrap2_async("env", "console_log", console_log)?;
+    linker.func_wrap3_async("env", "read", read)?;
+    linker.func_wrap2_async("env", "write", write)?;
+    linker.func_wrap3_async("env", "catalog_by_content_type", catalog_by_content_type)?;
+    linker.func_wrap3_async("env", "catalog_by_content_id", catalog_by_content_id)?;
+    linker.func_wrap4_async("env", "catalog_by_unique_id", catalog_by_unique_id)?;
+    linker.func_wrap5_async("env", "catalog_by_md5", catalog_by_md5)?;
+    linker.func_wrap3_async("env", "send_file", send_file)?;
+
+    let module = compile(&engine, &linker, &config.module)
+        .map_err(|e| format!("{}: {}", config.module.display(), e))?;
+    let (reloaded, module) = watch::channel(module);
+
+    /* The limit is shared by all listeners. */
+    let connections = shared
+        .limits
+        .max_connections
+        .map(|max_connections| Arc::new(Semaphore::new(max_connections)));
+
+    /* Listen for incoming TCP connections; bind all of them before accepting
+     * anything, so a typo in the config fails right away. */
+    let mut accepts = Vec::new();
+    for address in config.listen {
+        let listener = bind(address).map_err(|e| format!("{}: {}", address, e))?;
+        if shared.log_level >= LogLevel::Info {
+            println!("Listening on {}", address);
+        }
+        accepts.push(accept(
+            listener,
+            engine.clone(),
+            module.clone(),
+            linker.clone(),
+            shared.clone(),
+            connections.clone(),
+        ));
+    }
+
+    let hangup = signal(SignalKind::hangup())?;
+    let log_level = shared.log_level;
+    tokio::spawn(async move {
+        if let Err(e) = reload(hangup, config.module, engine, linker, reloaded, log_level).await {
+            println!("Stopped reloading on SIGHUP: {}", e);
+        }
+    });
+
+    futures::future::try_join_all(accepts).await?;
+    Ok(())
+}
+
 #[tokio::main()]
 async fn main() {
-    listen().await.unwrap();
+    let config = match config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = listen(config).await {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 }
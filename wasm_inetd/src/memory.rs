@@ -0,0 +1,60 @@
+use std::error::Error;
+use std::fmt;
+use wasmtime::{Caller, Extern};
+
+/* The guest handed the host something it can't use; there is no sensible
+ * way to continue, so the instance is stopped with this error. */
+#[derive(Debug, Clone, Copy)]
+pub enum MemoryError {
+    NoMemory,
+    OutOfBounds { ptr: i32, length: i32 },
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryError::NoMemory => write!(f, "guest exports no memory"),
+            MemoryError::OutOfBounds { ptr, length } => write!(
+                f,
+                "guest passed {} bytes at {}, which is outside its memory",
+                length, ptr
+            ),
+        }
+    }
+}
+
+impl Error for MemoryError {}
+
+fn range(data: &[u8], ptr: i32, length: i32) -> Result<std::ops::Range<usize>, MemoryError> {
+    let out_of_bounds = MemoryError::OutOfBounds { ptr, length };
+    let start = usize::try_from(ptr).map_err(|_| out_of_bounds)?;
+    let length = usize::try_from(length).map_err(|_| out_of_bounds)?;
+    let end = start.checked_add(length).ok_or(out_of_bounds)?;
+    if end > data.len() {
+        return Err(out_of_bounds);
+    }
+    Ok(start..end)
+}
+
+/* "length" bytes of guest memory at "ptr". */
+pub fn slice<'a, T>(
+    caller: &'a mut Caller<'_, T>,
+    ptr: i32,
+    length: i32,
+) -> Result<&'a [u8], MemoryError> {
+    slice_mut(caller, ptr, length).map(|data| &*data)
+}
+
+pub fn slice_mut<'a, T>(
+    caller: &'a mut Caller<'_, T>,
+    ptr: i32,
+    length: i32,
+) -> Result<&'a mut [u8], MemoryError> {
+    let mem = match caller.get_export("memory") {
+        Some(Extern::Memory(mem)) => mem,
+        _ => return Err(MemoryError::NoMemory),
+    };
+    let data = mem.data_mut(caller);
+    let range = range(data, ptr, length)?;
+    Ok(&mut data[range])
+}